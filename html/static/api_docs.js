@@ -0,0 +1,11 @@
+// renders the spec with the bundled Swagger UI, no request leaves the client
+window.onload = function () {
+    const container = document.getElementById("swagger-ui");
+    window.ui = SwaggerUIBundle({
+        url: container.dataset.specUrl,
+        dom_id: "#swagger-ui",
+        deepLinking: true,
+        presets: [SwaggerUIBundle.presets.apis],
+        layout: "BaseLayout",
+    });
+};
@@ -0,0 +1,10 @@
+// the pages are no templates, their forms get the csrf token of the master template here
+for (const form of document.querySelectorAll("form")) {
+    if (form.method === "post" && !form.elements.namedItem("csrf_token")) {
+        const input = document.createElement("input");
+        input.type = "hidden";
+        input.name = "csrf_token";
+        input.value = document.querySelector("meta[name=csrf-token]").content;
+        form.appendChild(input);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::config::{Command, ScanSettings};
+use crate::photogrammetry::image_handling::ImageDownloader;
+use crate::photogrammetry::downloads::DownloadStats;
+use crate::photogrammetry::photogrammetry::{self, OdmOptions, Preset};
+use crate::photogrammetry::paths;
+use crate::server_com::ServerError;
+use crate::web_interface::auth::{self, Role};
+use crate::web_interface::model::{ImageAppStatus, Order};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tokio::time::delay_for;
+
+const PROGRESS_INTERVAL: u64 = 1; // in seconds
+// lines of OpenDroneMap shown when the reconstruction fails
+const FAILURE_CONTEXT_LINES: usize = 20;
+// written by OpenDroneMap below the project folder
+const TEXTURE_FOLDER: &str = "odm_texturing";
+const GLB_FILE: &str = "odm_textured_model_geo.glb";
+
+/// Reason a subcommand failed, each with its own exit code.
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0}")]
+    Server(#[from] ServerError),
+    #[error("scan incomplete: {0}")]
+    Incomplete(String),
+    #[error("reconstruction failed: {0}")]
+    Reconstruction(String),
+    #[error("export failed: {0}")]
+    Export(String),
+    #[error("interrupted")]
+    Interrupted,
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Server(_) => 3,
+            CliError::Incomplete(_) => 4,
+            CliError::Reconstruction(_) => 5,
+            CliError::Export(_) => 6,
+            // as if the shell had stopped the process with SIGINT
+            CliError::Interrupted => 130,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    // glTF binary written by OpenDroneMap
+    Glb,
+    // the textured obj with its material and textures
+    Obj,
+    // the texture folder packed like the download of the web interface
+    Zip,
+}
+
+impl ExportFormat {
+    fn default_target(self) -> PathBuf {
+        match self {
+            ExportFormat::Glb => PathBuf::from("model.glb"),
+            ExportFormat::Obj => PathBuf::from("model"),
+            ExportFormat::Zip => PathBuf::from("model.zip"),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "glb" => Ok(ExportFormat::Glb),
+            "obj" => Ok(ExportFormat::Obj),
+            "zip" => Ok(ExportFormat::Zip),
+            _ => Err(format!("unknown format {}, expected glb, obj or zip", format)),
+        }
+    }
+}
+
+/// Runs the subcommand and returns the exit code of the process.
+pub async fn run(command: Command, settings: ScanSettings) -> i32 {
+    let result = match command {
+        Command::Scan { server_url, rounds, out } => scan(Order { server_url, rounds }, out, settings).await,
+        Command::Reconstruct { images, preset, odm_script } => reconstruct(&images, preset, odm_script).await,
+        Command::Export { project, format, out } => export(&project, format, out),
+        Command::HashPassword { user, role } => hash_password(&user, role),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            err.exit_code()
+        }
+    }
+}
+
+async fn scan(order: Order, out: PathBuf, settings: ScanSettings) -> Result<(), CliError> {
+    order.validate().map_err(CliError::Usage)?;
+    // the image store clears its folder before the scan starts
+    let is_empty = std::fs::read_dir(&out).map_or(true, |mut entries| entries.next().is_none());
+    if !is_empty {
+        return Err(CliError::Usage(format!("{} is not empty", out.display())));
+    }
+    paths::set_parent_folder(out)
+        .map_err(|out| CliError::Usage(format!("unable to scan into {}", out.display())))?;
+
+    let downloader = ImageDownloader::start_auftrag(
+        &order.server_url,
+        order.rounds.clone(),
+        settings,
+        Arc::new(std::sync::Mutex::new(None))).await
+        .map_err(|err| match err.downcast::<ServerError>() {
+            Ok(server_error) => CliError::Server(*server_error),
+            Err(err) => CliError::Usage(format!("unable to prepare {}: {}", paths::parent_folder().display(), err)),
+        })?;
+    let expected_images = downloader.expected_images();
+    println!("Auftrag with {} images started on {}", expected_images, downloader.server_url());
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut last_progress = String::new();
+    let (status, stats, image_count) = loop {
+        let status = downloader.get_status().await;
+        let stats = downloader.get_download_stats().await;
+        let image_count = downloader.get_image_list().await.len();
+        let progress = scan_progress(&status, &stats, image_count, expected_images);
+        if progress != last_progress {
+            println!("{}", progress);
+            last_progress = progress;
+        }
+        if status.is_done() && stats.queued + stats.active + stats.retrying == 0 {
+            break (status, stats, image_count);
+        }
+        tokio::select! {
+            _ = delay_for(Duration::from_secs(PROGRESS_INTERVAL)) => {}
+            _ = &mut ctrl_c => {
+                let report = downloader.cancel().await;
+                if !report.server_cancelled {
+                    eprintln!("the server did not confirm the abbruch, it may still be taking images");
+                }
+                return Err(CliError::Interrupted);
+            }
+        }
+    };
+
+    if status == ImageAppStatus::TimedOut {
+        return Err(CliError::Incomplete(format!(
+            "gave up after {} seconds with {} of {} images",
+            settings.scan_timeout.as_secs(), image_count, expected_images)));
+    }
+    if stats.failed > 0 || image_count < expected_images {
+        return Err(CliError::Incomplete(format!(
+            "{} of {} images downloaded, {} downloads failed", image_count, expected_images, stats.failed)));
+    }
+    println!("{} images saved in {}", image_count, paths::image_folder().display());
+    Ok(())
+}
+
+fn scan_progress(status: &ImageAppStatus, stats: &DownloadStats, image_count: usize, expected_images: usize) -> String {
+    let status = match status {
+        ImageAppStatus::Start => "waiting for the server".to_string(),
+        ImageAppStatus::TakingImages(server_status) => {
+            format!("round {}, shot {}", server_status.runde, server_status.aufnahme)
+        }
+        ImageAppStatus::Finished => "finished".to_string(),
+        ImageAppStatus::TimedOut => "timed out".to_string(),
+        ImageAppStatus::Paused => "paused".to_string(),
+    };
+    format!("{} | {}/{} images | {} downloading, {} retrying, {} failed",
+            status, image_count, expected_images, stats.active + stats.queued, stats.retrying, stats.failed)
+}
+
+async fn reconstruct(images: &Path, preset: Preset, odm_script: PathBuf) -> Result<(), CliError> {
+    let (project_path, project_name) = odm_project(images)?;
+    let options = OdmOptions {
+        script: odm_script,
+        project_path: Some(project_path.clone()),
+        project_name,
+        preset,
+        // the format the export prefers
+        gltf: true,
+    };
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let (mut console_reader, process) = photogrammetry::start_process(&options, shutdown_rx).await
+        .map_err(|err| CliError::Reconstruction(format!("unable to start OpenDroneMap: {}", err)))?;
+    println!("reconstructing {}", images.display());
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut last_lines = VecDeque::with_capacity(FAILURE_CONTEXT_LINES);
+    loop {
+        let line = tokio::select! {
+            line = console_reader.next_line() => line
+                .map_err(|err| CliError::Reconstruction(format!("unable to read the output of OpenDroneMap: {}", err)))?,
+            _ = &mut ctrl_c => {
+                // fails only if OpenDroneMap exited meanwhile
+                let _ = shutdown_tx.send(());
+                return Err(CliError::Interrupted);
+            }
+        };
+        let line = match line {
+            Some(line) => line,
+            // console pipe closed
+            None => break,
+        };
+        if let Some(stage) = photogrammetry::running_stage(&line) {
+            match photogrammetry::ODM_STAGES.iter().position(|known| *known == stage) {
+                Some(index) => println!("[{}/{}] {}", index + 1, photogrammetry::ODM_STAGES.len(), stage),
+                None => println!("[?/{}] {}", photogrammetry::ODM_STAGES.len(), stage),
+            }
+        }
+        if last_lines.len() == FAILURE_CONTEXT_LINES {
+            last_lines.pop_front();
+        }
+        last_lines.push_back(line);
+    }
+
+    let status = process.await
+        .map_err(|err| CliError::Reconstruction(format!("OpenDroneMap process exited with error: {}", err)))?;
+    drop(shutdown_tx);
+    match status {
+        Some(status) if status.success() => {
+            println!("model written to {}", project_path.join(&options.project_name).join(TEXTURE_FOLDER).display());
+            Ok(())
+        }
+        status => {
+            for line in last_lines {
+                eprintln!("{}", line);
+            }
+            let status = status.map_or_else(|| "unknown status".to_string(), |status| status.to_string());
+            Err(CliError::Reconstruction(format!("OpenDroneMap exited with {}", status)))
+        }
+    }
+}
+
+// OpenDroneMap expects the images in <project path>/<project name>/images
+fn odm_project(images: &Path) -> Result<(PathBuf, String), CliError> {
+    let images = images.canonicalize()
+        .map_err(|err| CliError::Usage(format!("unable to open {}: {}", images.display(), err)))?;
+    if images.file_name().is_none_or(|name| name != "images") {
+        return Err(CliError::Usage(format!(
+            "{} has to be named images, OpenDroneMap reads the images from <project>/images", images.display())));
+    }
+    let project = images.parent()
+        .ok_or_else(|| CliError::Usage("the images folder needs a project folder around it".to_string()))?;
+    match (project.parent(), project.file_name()) {
+        (Some(project_path), Some(project_name)) => {
+            Ok((project_path.to_path_buf(), project_name.to_string_lossy().into_owned()))
+        }
+        _ => Err(CliError::Usage("the project folder must not be the root folder".to_string())),
+    }
+}
+
+fn export(project: &Path, format: ExportFormat, out: Option<PathBuf>) -> Result<(), CliError> {
+    let texture_folder = project.join(TEXTURE_FOLDER);
+    if !texture_folder.is_dir() {
+        return Err(CliError::Export(format!(
+            "no model in {}, run reconstruct first", project.display())));
+    }
+    let target = out.unwrap_or_else(|| format.default_target());
+    match format {
+        ExportFormat::Glb => {
+            let glb_file = texture_folder.join(GLB_FILE);
+            if !glb_file.is_file() {
+                return Err(CliError::Export(format!(
+                    "{} is missing, the OpenDroneMap version used does not write glTF", glb_file.display())));
+            }
+            std::fs::copy(&glb_file, &target).map_err(|err| export_error(&target, err))?;
+        }
+        ExportFormat::Obj => {
+            std::fs::create_dir_all(&target).map_err(|err| export_error(&target, err))?;
+            let entries = std::fs::read_dir(&texture_folder).map_err(|err| export_error(&texture_folder, err))?;
+            for entry in entries {
+                let path = entry.map_err(|err| export_error(&texture_folder, err))?.path();
+                if let (true, Some(file_name)) = (path.is_file(), path.file_name()) {
+                    std::fs::copy(&path, target.join(file_name)).map_err(|err| export_error(&target, err))?;
+                }
+            }
+        }
+        ExportFormat::Zip => {
+            photogrammetry::zip_model(&texture_folder, &target).map_err(|err| export_error(&target, err))?;
+        }
+    }
+    println!("exported the model to {}", target.display());
+    Ok(())
+}
+
+fn export_error(path: &Path, err: impl std::fmt::Display) -> CliError {
+    CliError::Export(format!("unable to write {}: {}", path.display(), err))
+}
+
+fn hash_password(user: &str, role: Role) -> Result<(), CliError> {
+    if user.contains(':') {
+        return Err(CliError::Usage("the user name must not contain :".to_string()));
+    }
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)
+        .map_err(|err| CliError::Usage(format!("unable to read the password: {}", err)))?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err(CliError::Usage("the password must not be empty".to_string()));
+    }
+    let password_hash = auth::hash_password(password)
+        .map_err(|err| CliError::Usage(format!("unable to hash the password: {}", err)))?;
+    println!("{}:{}:{}", user, role.as_str(), password_hash);
+    Ok(())
+}
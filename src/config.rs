@@ -0,0 +1,353 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::time::Duration;
+use crate::server_com::Timeouts;
+use crate::discovery::{self, DiscoveryConfig};
+use crate::photogrammetry::downloads::DownloadConfig;
+use crate::photogrammetry::image_handling;
+use crate::web_interface::assets::HtmlSource;
+use crate::photogrammetry::photogrammetry::Preset;
+use crate::cli::ExportFormat;
+use crate::web_interface::auth::{AuthConfig, Role};
+use crate::web_interface::tls::{self, TlsConfig, TlsSource};
+use std::path::PathBuf;
+use std::net::Ipv4Addr;
+
+pub struct Config {
+    pub timeouts: Timeouts,
+    pub discovery: DiscoveryConfig,
+    pub downloads: DownloadConfig,
+    pub scan_timeout: Duration,
+    pub html: HtmlSource,
+    pub auth: AuthConfig,
+    // https is off if None
+    pub tls: Option<TlsConfig>,
+    // without a subcommand the web interface is served
+    pub command: Option<Command>,
+}
+
+/// Subcommands of the headless client, which work without the web interface.
+pub enum Command {
+    Scan { server_url: String, rounds: Vec<i32>, out: PathBuf },
+    Reconstruct { images: PathBuf, preset: Preset, odm_script: PathBuf },
+    Export { project: PathBuf, format: ExportFormat, out: Option<PathBuf> },
+    HashPassword { user: String, role: Role },
+}
+
+/// Settings a scan is run with, handed from phase to phase.
+#[derive(Clone, Copy, Debug)]
+pub struct ScanSettings {
+    pub timeouts: Timeouts,
+    pub downloads: DownloadConfig,
+    pub scan_timeout: Duration,
+}
+
+impl Config {
+    pub fn from_args() -> Config {
+        let matches = App::new("scaned_client")
+            .about("Client application of the ScanEd project")
+            .arg(Arg::with_name("connect-timeout")
+                .long("connect-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|value| parse_seconds(&value).map(|_| ()))
+                .help("Timeout for connecting to the ScanEd server"))
+            .arg(Arg::with_name("request-timeout")
+                .long("request-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|value| parse_seconds(&value).map(|_| ()))
+                .help("Timeout for a whole request to the ScanEd server"))
+            .arg(Arg::with_name("discovery-subnet")
+                .long("discovery-subnet")
+                .takes_value(true)
+                .value_name("CIDR")
+                .validator(|value| parse_subnet(&value).map(|_| ()))
+                .help("Subnet the discovery request is broadcast to, e.g. 192.168.1.0/24"))
+            .arg(Arg::with_name("discovery-port")
+                .long("discovery-port")
+                .takes_value(true)
+                .value_name("PORT")
+                .validator(|value| parse_port(&value).map(|_| ()))
+                .help("UDP port ScanEd servers listen on for discovery requests"))
+            .arg(Arg::with_name("download-concurrency")
+                .long("download-concurrency")
+                .takes_value(true)
+                .value_name("COUNT")
+                .validator(|value| parse_count(&value).map(|_| ()))
+                .help("Number of images downloaded from the ScanEd server at the same time"))
+            .arg(Arg::with_name("download-attempts")
+                .long("download-attempts")
+                .takes_value(true)
+                .value_name("COUNT")
+                .validator(|value| parse_count(&value).map(|_| ()))
+                .help("Number of attempts to download an image before giving up"))
+            .arg(Arg::with_name("scan-timeout")
+                .long("scan-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|value| parse_seconds(&value).map(|_| ()))
+                .help("Time after which waiting for the images of an Auftrag is given up"))
+            .arg(Arg::with_name("html-dir")
+                .long("html-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Reads templates and static files from DIR on every request instead of the embedded ones, for development"))
+            .arg(Arg::with_name("users")
+                .long("users")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Requires a login with the users in FILE, one name:role:argon2-hash per line"))
+            .arg(Arg::with_name("auth-token")
+                .long("auth-token")
+                .env("SCANED_AUTH_TOKEN")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("ROLE:TOKEN")
+                .validator(|token| parse_token(&token).map(|_| ()))
+                .help("Accepts TOKEN as bearer token or on the login page, ROLE is viewer, operator or admin"))
+            .arg(Arg::with_name("tls-cert")
+                .long("tls-cert")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("tls-key")
+                .conflicts_with("tls-self-signed")
+                .help("Serves https with the certificate chain in the PEM file FILE"))
+            .arg(Arg::with_name("tls-key")
+                .long("tls-key")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("tls-cert")
+                .help("Private key of --tls-cert as PEM file"))
+            .arg(Arg::with_name("tls-self-signed")
+                .long("tls-self-signed")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Serves https with a self-signed certificate, generated into DIR on the first start"))
+            .arg(Arg::with_name("tls-name")
+                .long("tls-name")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .requires("tls-self-signed")
+                .help("Host name or ip address the self-signed certificate is valid for, besides localhost"))
+            .arg(Arg::with_name("https-port")
+                .long("https-port")
+                .takes_value(true)
+                .value_name("PORT")
+                .validator(|value| parse_port(&value).map(|_| ()))
+                .help("Port of the https server, port 8080 then redirects to it (default 8443)"))
+            .subcommand(SubCommand::with_name("scan")
+                .about("Runs an Auftrag on a ScanEd server and downloads its images")
+                .arg(Arg::with_name("server")
+                    .long("server")
+                    .required(true)
+                    .takes_value(true)
+                    .value_name("URL")
+                    .help("Url of the ScanEd server, e.g. http://192.168.1.20:8000"))
+                .arg(Arg::with_name("rounds")
+                    .long("rounds")
+                    .required(true)
+                    .takes_value(true)
+                    .value_name("SHOTS,...")
+                    .validator(|rounds| parse_rounds(&rounds).map(|_| ()))
+                    .help("Number of shots of each round, e.g. 12,12,8"))
+                .arg(Arg::with_name("out")
+                    .long("out")
+                    .required(true)
+                    .takes_value(true)
+                    .value_name("DIR")
+                    .help("Empty or missing folder the scan is stored in, the images end up in DIR/images")))
+            .subcommand(SubCommand::with_name("reconstruct")
+                .about("Reconstructs a model from the images with OpenDroneMap")
+                .arg(Arg::with_name("images")
+                    .long("images")
+                    .required(true)
+                    .takes_value(true)
+                    .value_name("DIR")
+                    .help("Folder named images, the model is written next to it"))
+                .arg(Arg::with_name("preset")
+                    .long("preset")
+                    .takes_value(true)
+                    .possible_values(&["fast", "default", "high"])
+                    .default_value("default")
+                    .help("Trade-off between reconstruction time and detail of the model"))
+                .arg(Arg::with_name("odm-script")
+                    .long("odm-script")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .default_value("run.py")
+                    .help("run.py of OpenDroneMap")))
+            .subcommand(SubCommand::with_name("export")
+                .about("Copies the reconstructed model out of the project folder")
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["glb", "obj", "zip"]))
+                .arg(Arg::with_name("project")
+                    .long("project")
+                    .takes_value(true)
+                    .value_name("DIR")
+                    .default_value(".")
+                    .help("Folder containing the images folder which was reconstructed"))
+                .arg(Arg::with_name("out")
+                    .long("out")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("Target file, a folder for obj, defaults to model.<format> in the current folder")))
+            .subcommand(SubCommand::with_name("hash-password")
+                .about("Reads a password from stdin and prints the line for the --users file")
+                .arg(Arg::with_name("user")
+                    .long("user")
+                    .required(true)
+                    .takes_value(true)
+                    .value_name("NAME"))
+                .arg(Arg::with_name("role")
+                    .long("role")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["viewer", "operator", "admin"])))
+            .get_matches();
+
+        let default_timeouts = Timeouts::default();
+        Config {
+            timeouts: Timeouts {
+                connect: seconds_arg(&matches, "connect-timeout").unwrap_or(default_timeouts.connect),
+                request: seconds_arg(&matches, "request-timeout").unwrap_or(default_timeouts.request),
+            },
+            discovery: discovery_arg(&matches),
+            downloads: download_arg(&matches),
+            scan_timeout: seconds_arg(&matches, "scan-timeout")
+                .unwrap_or_else(|| Duration::from_secs(image_handling::DEFAULT_SCAN_TIMEOUT)),
+            html: matches.value_of("html-dir")
+                .map_or(HtmlSource::Embedded, |dir| HtmlSource::Directory(PathBuf::from(dir))),
+            auth: AuthConfig {
+                users_file: matches.value_of("users").map(PathBuf::from),
+                tokens: matches.values_of("auth-token")
+                    .map_or_else(Vec::new, |tokens| tokens.filter_map(|token| parse_token(token).ok()).collect()),
+            },
+            tls: tls_arg(&matches),
+            command: command_arg(&matches),
+        }
+    }
+
+    pub fn scan_settings(&self) -> ScanSettings {
+        ScanSettings { timeouts: self.timeouts, downloads: self.downloads, scan_timeout: self.scan_timeout }
+    }
+}
+
+// values were checked by clap
+fn command_arg(matches: &ArgMatches) -> Option<Command> {
+    let path = |matches: &ArgMatches, name| matches.value_of(name).map(PathBuf::from);
+    match matches.subcommand() {
+        ("scan", Some(matches)) => Some(Command::Scan {
+            server_url: matches.value_of("server")?.to_string(),
+            rounds: parse_rounds(matches.value_of("rounds")?).ok()?,
+            out: path(matches, "out")?,
+        }),
+        ("reconstruct", Some(matches)) => Some(Command::Reconstruct {
+            images: path(matches, "images")?,
+            preset: matches.value_of("preset")?.parse().ok()?,
+            odm_script: path(matches, "odm-script")?,
+        }),
+        ("export", Some(matches)) => Some(Command::Export {
+            project: path(matches, "project")?,
+            format: matches.value_of("format")?.parse().ok()?,
+            out: path(matches, "out"),
+        }),
+        ("hash-password", Some(matches)) => Some(Command::HashPassword {
+            user: matches.value_of("user")?.to_string(),
+            role: matches.value_of("role")?.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+fn parse_rounds(rounds: &str) -> Result<Vec<i32>, String> {
+    rounds.split(',')
+        .map(|shots| shots.trim().parse::<i32>()
+            .map_err(|_| format!("expected the number of shots of each round like 12,12,8, got {}", rounds)))
+        .collect()
+}
+
+fn tls_arg(matches: &ArgMatches) -> Option<TlsConfig> {
+    let source = match (matches.value_of("tls-cert"), matches.value_of("tls-key"), matches.value_of("tls-self-signed")) {
+        (Some(cert), Some(key), _) => TlsSource::Pem { cert: PathBuf::from(cert), key: PathBuf::from(key) },
+        (_, _, Some(dir)) => TlsSource::SelfSigned {
+            dir: PathBuf::from(dir),
+            names: matches.values_of("tls-name").map_or_else(Vec::new, |names| names.map(str::to_string).collect()),
+        },
+        _ => return None,
+    };
+    let https_port = matches.value_of("https-port")
+        .and_then(|port| parse_port(port).ok())
+        .unwrap_or(tls::DEFAULT_HTTPS_PORT);
+    Some(TlsConfig { source, https_port })
+}
+
+fn parse_token(token: &str) -> Result<(Role, String), String> {
+    let mut parts = token.splitn(2, ':');
+    match (parts.next().map(str::parse::<Role>), parts.next()) {
+        (Some(Ok(role)), Some(token)) if !token.is_empty() => Ok((role, token.to_string())),
+        (Some(Err(err)), _) => Err(err),
+        _ => Err("expected ROLE:TOKEN, e.g. operator:secret".to_string()),
+    }
+}
+
+fn download_arg(matches: &ArgMatches) -> DownloadConfig {
+    let default_downloads = DownloadConfig::default();
+    DownloadConfig {
+        concurrency: count_arg(matches, "download-concurrency").unwrap_or(default_downloads.concurrency),
+        max_attempts: count_arg(matches, "download-attempts").map_or(default_downloads.max_attempts, |attempts| attempts as u32),
+    }
+}
+
+// the values of these arguments were checked by their validators
+fn count_arg(matches: &ArgMatches, name: &str) -> Option<usize> {
+    matches.value_of(name).and_then(|value| parse_count(value).ok())
+}
+
+fn discovery_arg(matches: &ArgMatches) -> DiscoveryConfig {
+    let default_discovery = DiscoveryConfig::default();
+    DiscoveryConfig {
+        broadcast_address: matches.value_of("discovery-subnet")
+            .and_then(|subnet| parse_subnet(subnet).ok())
+            .unwrap_or(default_discovery.broadcast_address),
+        port: matches.value_of("discovery-port")
+            .and_then(|port| parse_port(port).ok())
+            .unwrap_or(default_discovery.port),
+    }
+}
+
+fn seconds_arg(matches: &ArgMatches, name: &str) -> Option<Duration> {
+    matches.value_of(name)
+        .and_then(|value| parse_seconds(value).ok())
+        .map(Duration::from_secs)
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("expected a positive number, got {}", value)),
+    }
+}
+
+fn parse_port(value: &str) -> Result<u16, String> {
+    value.parse::<u16>().map_err(|_| format!("expected a port number, got {}", value))
+}
+
+// a timeout of 0 would fail every request at once
+fn parse_seconds(value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err("expected at least 1 second, got 0".to_string()),
+        Ok(seconds) => Ok(seconds),
+        Err(_) => Err(format!("expected a number of seconds, got {}", value)),
+    }
+}
+
+fn parse_subnet(value: &str) -> Result<Ipv4Addr, String> {
+    discovery::broadcast_address(value)
+        .ok_or_else(|| format!("expected a subnet like 192.168.1.0/24, got {}", value))
+}
@@ -0,0 +1,131 @@
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use log::{info, warn};
+use futures::future;
+use crate::server_com::{ScanEdClient, Timeouts};
+use crate::web_interface::health::{self, ConnectionCheck};
+
+pub const DISCOVERY_REQUEST: &[u8] = b"SCANED_DISCOVER";
+pub const DEFAULT_DISCOVERY_PORT: u16 = 8001;
+const DISCOVERY_WINDOW: u64 = 2; // in seconds
+
+/// Answer of a ScanEd server to the discovery broadcast.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DiscoveryResponse {
+    pub name: String,
+    pub port: u16,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DiscoveredServer {
+    pub name: String,
+    pub url: String,
+    pub connection: ConnectionCheck,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DiscoveryConfig {
+    pub broadcast_address: Ipv4Addr,
+    pub port: u16,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig { broadcast_address: Ipv4Addr::BROADCAST, port: DEFAULT_DISCOVERY_PORT }
+    }
+}
+
+/// Broadcast address of a subnet in cidr notation, e.g. 192.168.1.0/24.
+pub fn broadcast_address(subnet: &str) -> Option<Ipv4Addr> {
+    let mut parts = subnet.splitn(2, '/');
+    let address = Ipv4Addr::from_str(parts.next()?).ok()?;
+    let prefix = parts.next().map_or(Some(32), |prefix| prefix.parse::<u32>().ok())?;
+    if prefix > 32 {
+        return None;
+    }
+    let host_mask = u32::MAX.checked_shr(prefix).unwrap_or(0);
+    Some(Ipv4Addr::from(u32::from(address) | host_mask))
+}
+
+/// Broadcasts a discovery request and checks the status of every server which answers.
+pub async fn discover(config: DiscoveryConfig, timeouts: Timeouts) -> std::io::Result<Vec<DiscoveredServer>> {
+    let mut socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?;
+    socket.set_broadcast(true)?;
+    socket.send_to(DISCOVERY_REQUEST, SocketAddr::from((config.broadcast_address, config.port))).await?;
+    info!("sent discovery request to {}:{}", config.broadcast_address, config.port);
+
+    let mut responses = Vec::new();
+    let mut buf = [0u8; 1024];
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(DISCOVERY_WINDOW);
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, source) = received?;
+        match serde_json::from_slice::<DiscoveryResponse>(&buf[..len]) {
+            Ok(response) => {
+                let url = format!("http://{}:{}", source.ip(), response.port);
+                if responses.iter().all(|(_, known_url)| known_url != &url) {
+                    responses.push((response.name, url));
+                }
+            }
+            Err(err) => warn!("invalid discovery response from {}: {}", source, err),
+        }
+    }
+
+    // every server is checked at the same time, unreachable ones would add up their timeouts
+    Ok(future::join_all(responses.into_iter().map(|(name, url)| async move {
+        let connection = match ScanEdClient::new(&url, timeouts) {
+            Ok(client) => health::check_connection(&client).await,
+            Err(err) => ConnectionCheck { reachable: false, latency_ms: None, status: None, error: Some(err.to_string()) },
+        };
+        DiscoveredServer { name, url, connection }
+    })).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // port on localhost where nothing listens
+    fn closed_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn servers_answering_on_localhost_are_discovered() {
+        let mut responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = DiscoveryConfig {
+            broadcast_address: Ipv4Addr::LOCALHOST,
+            port: responder.local_addr().unwrap().port(),
+        };
+        let ports = [closed_port(), closed_port()];
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            let (len, client) = responder.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], DISCOVERY_REQUEST);
+            let answers = [
+                serde_json::to_vec(&DiscoveryResponse { name: "pi-a".to_string(), port: ports[0] }).unwrap(),
+                serde_json::to_vec(&DiscoveryResponse { name: "pi-a".to_string(), port: ports[0] }).unwrap(),
+                b"no json".to_vec(),
+                serde_json::to_vec(&DiscoveryResponse { name: "pi-b".to_string(), port: ports[1] }).unwrap(),
+            ];
+            for answer in answers.iter() {
+                responder.send_to(answer, client).await.unwrap();
+            }
+        });
+
+        let timeouts = Timeouts { connect: Duration::from_secs(1), request: Duration::from_secs(1) };
+        let mut servers = discover(config, timeouts).await.unwrap();
+        servers.sort_by(|a, b| a.name.cmp(&b.name));
+        let found = servers.iter()
+            .map(|server| (server.name.as_str(), server.url.clone(), server.connection.reachable))
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            ("pi-a", format!("http://127.0.0.1:{}", ports[0]), false),
+            ("pi-b", format!("http://127.0.0.1:{}", ports[1]), false),
+        ]);
+        assert!(servers.iter().all(|server| server.connection.error.is_some()));
+    }
+}
@@ -0,0 +1,178 @@
+use actix_web::web::Bytes;
+
+// drop buffered data if no complete frame shows up within this many bytes
+const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Splits a multipart/x-mixed-replace body into its jpeg frames.
+pub struct MjpegParser {
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl MjpegParser {
+    pub fn new(boundary: &str) -> MjpegParser {
+        // some servers already prefix the boundary parameter with the dashes
+        let delimiter = if boundary.starts_with("--") {
+            boundary.to_string()
+        } else {
+            format!("--{}", boundary)
+        };
+        MjpegParser { delimiter: delimiter.into_bytes(), buf: Vec::new() }
+    }
+
+    /// Reads the boundary from a content type like `multipart/x-mixed-replace; boundary=frame`.
+    pub fn from_content_type(content_type: &str) -> Option<MjpegParser> {
+        if !content_type.trim_start().to_ascii_lowercase().starts_with("multipart/") {
+            return None;
+        }
+        content_type.split(';')
+            .filter_map(|parameter| {
+                let mut parameter = parameter.splitn(2, '=');
+                match (parameter.next()?.trim(), parameter.next()) {
+                    (key, Some(value)) if key.eq_ignore_ascii_case("boundary") => Some(value.trim().trim_matches('"')),
+                    _ => None,
+                }
+            })
+            .find(|boundary| !boundary.is_empty())
+            .map(MjpegParser::new)
+    }
+
+    /// Adds a chunk of the body and returns every frame which is complete now.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Bytes> {
+        self.buf.extend_from_slice(chunk);
+        let mut frames = Vec::new();
+        while let Some(frame) = self.next_frame() {
+            frames.push(frame);
+        }
+        if self.buf.len() > MAX_BUFFER_SIZE {
+            self.buf.clear();
+        }
+        frames
+    }
+
+    fn next_frame(&mut self) -> Option<Bytes> {
+        let part_start = find(&self.buf, &self.delimiter, 0)?;
+        let header_end = find(&self.buf, b"\r\n\r\n", part_start)?;
+        let body_start = header_end + 4;
+        let headers = String::from_utf8_lossy(&self.buf[part_start + self.delimiter.len()..header_end]).to_string();
+
+        let body_end = match content_length(&headers) {
+            Some(length) if self.buf.len() >= body_start + length => body_start + length,
+            Some(_) => return None,
+            None => {
+                let next_part = find(&self.buf, &self.delimiter, body_start)?;
+                trim_line_break(&self.buf, body_start, next_part)
+            }
+        };
+        let frame = Bytes::copy_from_slice(&self.buf[body_start..body_end]);
+        self.buf.drain(..body_end);
+        Some(frame)
+    }
+}
+
+fn content_length(headers: &str) -> Option<usize> {
+    headers.lines()
+        .filter_map(|line| {
+            let mut header = line.splitn(2, ':');
+            match (header.next()?.trim(), header.next()) {
+                (name, Some(value)) if name.eq_ignore_ascii_case("content-length") => value.trim().parse().ok(),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn trim_line_break(buf: &[u8], start: usize, mut end: usize) -> usize {
+    while end > start && (buf[end - 1] == b'\n' || buf[end - 1] == b'\r') {
+        end -= 1;
+    }
+    end
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if haystack.len() < from + needle.len() {
+        return None;
+    }
+    haystack[from..].windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(boundary: &str, body: &[u8], with_length: bool) -> Vec<u8> {
+        let mut part = format!("--{}\r\nContent-Type: image/jpeg\r\n", boundary).into_bytes();
+        if with_length {
+            part.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+        }
+        part.extend_from_slice(b"\r\n");
+        part.extend_from_slice(body);
+        part.extend_from_slice(b"\r\n");
+        part
+    }
+
+    #[test]
+    fn frames_split_across_chunks() {
+        let mut stream = part("frame", b"first", true);
+        stream.extend(part("frame", b"second", true));
+        // one byte per chunk splits every delimiter and header as well
+        let mut parser = MjpegParser::new("frame");
+        let frames = stream.iter().flat_map(|byte| parser.push(&[*byte])).collect::<Vec<_>>();
+        assert_eq!(frames, vec![Bytes::from_static(b"first"), Bytes::from_static(b"second")]);
+
+        let mut parser = MjpegParser::new("frame");
+        assert!(parser.push(&stream[..3]).is_empty());
+        assert_eq!(parser.push(&stream[3..]), vec![Bytes::from_static(b"first"), Bytes::from_static(b"second")]);
+    }
+
+    #[test]
+    fn parts_with_and_without_content_length() {
+        let mut parser = MjpegParser::new("frame");
+        // the length allows line breaks at the end of the jpeg data
+        assert_eq!(parser.push(&part("frame", b"data\r\n", true)), vec![Bytes::from_static(b"data\r\n")]);
+
+        // without a length a part ends at the next delimiter
+        assert!(parser.push(&part("frame", b"first", false)).is_empty());
+        assert_eq!(parser.push(&part("frame", b"second", false)), vec![Bytes::from_static(b"first")]);
+        assert_eq!(parser.push(b"--frame--\r\n"), vec![Bytes::from_static(b"second")]);
+    }
+
+    #[test]
+    fn boundaries_with_and_without_dashes() {
+        let mut stream = part("frame", b"first", true);
+        stream.extend(part("frame", b"second", false));
+        stream.extend_from_slice(b"--frame--\r\n");
+        for boundary in &["frame", "--frame"] {
+            let mut parser = MjpegParser::new(boundary);
+            assert_eq!(parser.push(&stream), vec![Bytes::from_static(b"first"), Bytes::from_static(b"second")]);
+        }
+    }
+
+    #[test]
+    fn several_frames_in_one_chunk() {
+        let stream = (0..5).flat_map(|i| part("frame", format!("frame {}", i).as_bytes(), true)).collect::<Vec<_>>();
+        let mut parser = MjpegParser::new("frame");
+        let frames = parser.push(&stream);
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[4], Bytes::from_static(b"frame 4"));
+    }
+
+    #[test]
+    fn boundary_from_the_content_type() {
+        let stream = part("frame", b"data", true);
+        for content_type in &[
+            "multipart/x-mixed-replace; boundary=frame",
+            "multipart/x-mixed-replace;boundary=\"frame\"",
+            "Multipart/X-Mixed-Replace; charset=utf-8; Boundary=--frame",
+        ] {
+            let mut parser = MjpegParser::from_content_type(content_type).unwrap();
+            assert_eq!(parser.push(&stream), vec![Bytes::from_static(b"data")], "{}", content_type);
+        }
+        assert!(MjpegParser::from_content_type("multipart/x-mixed-replace").is_none());
+        assert!(MjpegParser::from_content_type("multipart/x-mixed-replace; boundary=").is_none());
+        assert!(MjpegParser::from_content_type("multipart/x-mixed-replace; boundary=\"\"").is_none());
+        assert!(MjpegParser::from_content_type("image/jpeg; boundary=frame").is_none());
+    }
+}
@@ -0,0 +1,150 @@
+pub mod mjpeg;
+pub mod snapshot;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::web::Bytes;
+use actix_web::rt::time::delay_for;
+use futures::Stream;
+use reqwest::{Client, Url};
+use tokio::sync::broadcast;
+use log::{info, warn};
+use crate::livestream::mjpeg::MjpegParser;
+
+// the pi serves its camera stream on a separate port
+pub const STREAM_PORT: u16 = 8080;
+pub const STREAM_PATH: &str = "/stream/video.mjpeg";
+pub const BOUNDARY: &str = "frame";
+
+const RECONNECT_DELAY: u64 = 2; // in seconds
+const FRAME_BUFFER: usize = 4; // viewers falling further behind skip frames
+
+/// Stream url of the pi which also serves the api at the given url.
+pub fn stream_url(server_url: &Url) -> Url {
+    let mut stream_url = server_url.clone();
+    stream_url.set_path(STREAM_PATH);
+    stream_url.set_query(None);
+    // only fails for urls without a host, server urls always have one
+    let _ = stream_url.set_port(Some(STREAM_PORT));
+    stream_url
+}
+
+type Upstreams = Arc<std::sync::Mutex<HashMap<Url, broadcast::Sender<Bytes>>>>;
+
+/// Shares one upstream connection per stream url between all viewers. An entry exists exactly as
+/// long as its upstream task runs, the task removes it under the same lock subscribe takes.
+pub struct LiveStreamHub {
+    client: Client,
+    upstreams: Upstreams,
+}
+
+impl LiveStreamHub {
+    pub fn new(connect_timeout: Duration) -> LiveStreamHub {
+        LiveStreamHub {
+            // no request timeout, the stream does not end
+            client: Client::builder()
+                .connect_timeout(connect_timeout)
+                .build()
+                .expect("unable to build livestream http client"),
+            upstreams: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn subscribe(&self, url: Url) -> broadcast::Receiver<Bytes> {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        if let Some(sender) = upstreams.get(&url) {
+            return sender.subscribe();
+        }
+        info!("opening livestream upstream {}", url);
+        let (sender, receiver) = broadcast::channel(FRAME_BUFFER);
+        upstreams.insert(url.clone(), sender.clone());
+        tokio::spawn(run_upstream(self.client.clone(), url, sender, Arc::clone(&self.upstreams)));
+        receiver
+    }
+}
+
+async fn run_upstream(client: Client, url: Url, sender: broadcast::Sender<Bytes>, upstreams: Upstreams) {
+    loop {
+        match forward_frames(&client, &url, &sender).await {
+            Ok(()) => info!("livestream upstream {} closed", url),
+            Err(err) => warn!("livestream upstream {} failed: {}", url, err),
+        }
+        if release(&upstreams, &url, &sender) {
+            break;
+        }
+        delay_for(Duration::from_secs(RECONNECT_DELAY)).await;
+    }
+    info!("no viewers left, stopped livestream upstream {}", url);
+}
+
+// removes the upstream once no viewer is left, a viewer subscribing meanwhile keeps it running
+fn release(upstreams: &Upstreams, url: &Url, sender: &broadcast::Sender<Bytes>) -> bool {
+    let mut upstreams = upstreams.lock().unwrap();
+    if sender.receiver_count() > 0 {
+        return false;
+    }
+    upstreams.remove(url);
+    true
+}
+
+async fn forward_frames(client: &Client, url: &Url, sender: &broadcast::Sender<Bytes>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut response = client.get(url.clone()).send().await?.error_for_status()?;
+    let content_type = response.headers().get("content-type")
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let mut parser = MjpegParser::from_content_type(&content_type)
+        .ok_or_else(|| format!("not a mjpeg stream: {}", content_type))?;
+    while let Some(chunk) = response.chunk().await? {
+        for frame in parser.push(&chunk) {
+            if sender.send(frame).is_err() {
+                // every viewer is gone
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Multipart body for a single viewer, see BOUNDARY.
+pub fn viewer_stream(receiver: broadcast::Receiver<Bytes>) -> impl Stream<Item=Result<Bytes, actix_web::Error>> + Unpin {
+    Box::pin(futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(frame) => {
+                    let mut part = format!(
+                        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n", BOUNDARY, frame.len()
+                    ).into_bytes();
+                    part.extend_from_slice(&frame);
+                    part.extend_from_slice(b"\r\n");
+                    return Some((Ok(Bytes::from(part)), receiver));
+                }
+                Err(broadcast::RecvError::Lagged(_)) => continue,
+                Err(broadcast::RecvError::Closed) => return None,
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn upstreams_without_viewers_are_removed() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let url = Url::parse(&format!("http://127.0.0.1:{}{}", port, STREAM_PATH)).unwrap();
+        let hub = LiveStreamHub::new(Duration::from_secs(1));
+        let receiver = hub.subscribe(url.clone());
+        // the refused connection is retried while the viewer stays
+        delay_for(Duration::from_millis(200)).await;
+        assert!(hub.upstreams.lock().unwrap().contains_key(&url));
+        let second = hub.subscribe(url.clone());
+        drop(receiver);
+        drop(second);
+        delay_for(Duration::from_millis(RECONNECT_DELAY * 1000 + 500)).await;
+        assert!(hub.upstreams.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::web::Bytes;
+use reqwest::Url;
+use tokio::sync::broadcast;
+use crate::livestream::LiveStreamHub;
+use crate::photogrammetry::quality::{ImageQuality, QualityFlag};
+
+const SNAPSHOT_TIMEOUT: u64 = 10; // in seconds
+const DARK_BRIGHTNESS: f64 = 0.3;
+const BRIGHT_BRIGHTNESS: f64 = 0.7;
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub enum ExposureHint {
+    TooDark,
+    Dark,
+    Bright,
+    TooBright,
+    Blurry,
+    Good,
+}
+
+/// Single frame of the livestream to check framing and lighting before an Auftrag.
+pub struct Preview {
+    pub jpeg: Bytes,
+    pub report: PreviewReport,
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct PreviewReport {
+    pub taken_at: u64, // unix timestamp in seconds
+    pub mean_brightness: f64,
+    pub blur: f64,
+    pub histogram: Vec<f64>,
+    pub hints: Vec<ExposureHint>,
+}
+
+/// Waits for the next frame of the stream and analyses its exposure.
+pub async fn take_snapshot(hub: &LiveStreamHub, stream_url: Url) -> Result<Preview, String> {
+    let mut receiver = hub.subscribe(stream_url);
+    let frame = loop {
+        match tokio::time::timeout(Duration::from_secs(SNAPSHOT_TIMEOUT), receiver.recv()).await {
+            Ok(Ok(frame)) => break frame,
+            Ok(Err(broadcast::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::RecvError::Closed)) => return Err("livestream closed".to_string()),
+            Err(_) => return Err("no frame received from the livestream".to_string()),
+        }
+    };
+    let quality = ImageQuality::analyse(frame.to_vec()).await
+        .map_err(|err| format!("unable to decode frame: {}", err))?;
+    Ok(Preview {
+        jpeg: frame,
+        report: PreviewReport {
+            taken_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            mean_brightness: quality.mean_brightness,
+            blur: quality.blur,
+            hints: exposure_hints(&quality),
+            histogram: quality.histogram,
+        },
+    })
+}
+
+fn exposure_hints(quality: &ImageQuality) -> Vec<ExposureHint> {
+    let flags = quality.flags();
+    let mut hints = Vec::new();
+    if flags.contains(&QualityFlag::Underexposed) {
+        hints.push(ExposureHint::TooDark);
+    } else if quality.mean_brightness < DARK_BRIGHTNESS {
+        hints.push(ExposureHint::Dark);
+    }
+    if flags.contains(&QualityFlag::Overexposed) {
+        hints.push(ExposureHint::TooBright);
+    } else if quality.mean_brightness > BRIGHT_BRIGHTNESS {
+        hints.push(ExposureHint::Bright);
+    }
+    if flags.contains(&QualityFlag::Blurry) {
+        hints.push(ExposureHint::Blurry);
+    }
+    if hints.is_empty() {
+        hints.push(ExposureHint::Good);
+    }
+    hints
+}
@@ -1,74 +1,338 @@
 mod web_interface;
 mod server_com;
 mod photogrammetry;
+mod config;
+mod discovery;
+mod livestream;
+mod cli;
 
 use std::str::FromStr;
 use actix_web::{HttpServer, App, web};
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
 use crate::web_interface::app_state::AppState;
 use crate::web_interface::app_state;
+use crate::web_interface::api;
 use tokio::sync::{Mutex};
-use log::{info};
+use log::{info, warn};
+use crate::config::Config;
+use crate::livestream::LiveStreamHub;
+use crate::livestream::snapshot::Preview;
+use crate::web_interface::templates::TemplateRegistry;
+use crate::web_interface::assets::StaticAssets;
+use crate::web_interface::auth::{self, Authenticator};
+use crate::web_interface::tls;
+use crate::web_interface::csrf::{self, CsrfGuard};
+use actix_web::dev::Service;
+use futures::future::{self, Either};
 
 mod endpoints {
-    use actix_web::{Responder, web, get, post, delete, HttpRequest, HttpResponse};
+    use actix_web::{Responder, web, get, post, HttpRequest, HttpResponse};
     use crate::AppData;
-    use crate::web_interface::model::{PageForm};
-    use log::{info};
+    use crate::web_interface::model::{PageFormPost, CsrfForm, ServerUrlQuery, HealthMonitorQuery, LiveStreamQuery, ErrorKind, Credentials};
+    use crate::livestream::{self, snapshot};
+    use crate::livestream::snapshot::PreviewReport;
+    use crate::web_interface::health::{self, ConnectionCheck};
+    use std::time::Duration;
+    use crate::web_interface::app_state::{server_error_response, error_response};
+    use crate::server_com::{self, ScanEdClient};
+    use crate::discovery::{self, DiscoveredServer};
+    use crate::server_com::com_model;
+    use crate::web_interface::api::Route;
+    use crate::web_interface::openapi::{ok, empty, binary};
+    use crate::web_interface::templates::{TemplateRegistry, LoginContext};
+    use crate::web_interface::assets::StaticAssets;
+    use crate::web_interface::auth::{self, Authenticator, Identity};
+    use crate::web_interface::csrf::CsrfGuard;
+    use actix_web::http::{header, Method, StatusCode};
+    use log::{info, error};
+    use serde_json::{json, Value};
+
+    fn url_parameter(required: bool) -> Value {
+        json!({ "name": "url", "in": "query", "required": required, "schema": { "type": "string" },
+                "description": "Adresse des ScanEd-Servers" })
+    }
+
+    /// Routes which the scripts of the pages call, besides the json api.
+    pub(crate) const ROUTES: &[Route] = &[
+        Route {
+            method: Method::POST, path: "/page_form", handler: "post_page_form",
+            summary: "Formular der html-Seiten, mit dem CSRF-Token der Seite",
+            to: |route| route.to(post_page_form),
+            operation: |spec| json!({
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/x-www-form-urlencoded": { "schema": spec.schema::<PageFormPost>() } },
+                },
+                "responses": { "303": empty("Weiter zur Seite der nächsten Phase") },
+            }),
+        },
+        Route {
+            method: Method::GET, path: "/server/info", handler: "server_info", summary: "Version und Funktionen eines Servers",
+            to: |route| route.to(server_info),
+            operation: |spec| json!({
+                "parameters": [url_parameter(true)],
+                "responses": { "200": ok("Antwort des Servers", spec.json_content::<com_model::ServerInfo>()) },
+            }),
+        },
+        Route {
+            method: Method::GET, path: "/server/check", handler: "server_check", summary: "Verbindung zu einem Server prüfen",
+            to: |route| route.to(server_check),
+            operation: |spec| json!({
+                "parameters": [url_parameter(true)],
+                "responses": { "200": ok("Ergebnis, auch wenn der Server nicht erreichbar ist", spec.json_content::<ConnectionCheck>()) },
+            }),
+        },
+        Route {
+            method: Method::GET, path: "/server/health", handler: "server_health", summary: "Verbindung laufend prüfen",
+            to: |route| route.to(server_health),
+            operation: |_| json!({
+                "parameters": [
+                    url_parameter(true),
+                    { "name": "interval", "in": "query", "required": false, "schema": { "type": "integer", "minimum": 1 },
+                      "description": "Sekunden zwischen den Prüfungen" },
+                ],
+                "responses": {
+                    "200": ok("Server-sent events mit einer ConnectionCheck je Prüfung", json!({ "text/event-stream": {} })),
+                },
+            }),
+        },
+        Route {
+            method: Method::GET, path: "/server/discover", handler: "server_discover", summary: "Server im Netzwerk suchen",
+            to: |route| route.to(server_discover),
+            operation: |spec| json!({
+                "responses": { "200": ok("Server, die geantwortet haben", spec.json_content::<Vec<DiscoveredServer>>()) },
+            }),
+        },
+        Route {
+            method: Method::GET, path: "/livestream", handler: "get_livestream", summary: "Livestream der Kamera",
+            to: |route| route.to(get_livestream),
+            operation: |_| json!({
+                "parameters": [url_parameter(false)],
+                "responses": { "200": ok("MJPEG, ohne url vom Server des Auftrags", json!({ "multipart/x-mixed-replace": {} })) },
+            }),
+        },
+        Route {
+            method: Method::POST, path: "/snapshot", handler: "take_snapshot", summary: "Einzelbild des Livestreams prüfen",
+            to: |route| route.to(take_snapshot),
+            operation: |spec| json!({
+                "parameters": [url_parameter(false)],
+                "responses": { "200": ok("Belichtung des Bildes", spec.json_content::<PreviewReport>()) },
+            }),
+        },
+        Route {
+            method: Method::GET, path: "/snapshot", handler: "get_snapshot", summary: "Letztes Einzelbild",
+            to: |route| route.to(get_snapshot),
+            operation: |spec| json!({ "responses": { "200": ok("Belichtung des Bildes", spec.json_content::<PreviewReport>()) } }),
+        },
+        Route {
+            method: Method::GET, path: "/snapshot/image", handler: "get_snapshot_image", summary: "Letztes Einzelbild als JPEG",
+            to: |route| route.to(get_snapshot_image),
+            operation: |_| json!({ "responses": { "200": ok("Einzelbild", binary("image/jpeg")) } }),
+        },
+        Route {
+            method: Method::GET, path: "/ws_notification", handler: "ws_notification", summary: "Benachrichtigungen der Seiten",
+            to: |route| route.to(ws_notification),
+            operation: |_| json!({ "responses": { "101": empty("WebSocket, meldet neue Aufnahmen und Statusänderungen") } }),
+        },
+    ];
+
+    pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+        for route in ROUTES {
+            cfg.service(route.service());
+        }
+    }
 
     #[get("/")]
-    pub(crate) async fn index(data: web::Data<AppData>) -> impl Responder {
+    pub(crate) async fn index(data: web::Data<AppData>, templates: web::Data<TemplateRegistry>, csrf: web::Data<CsrfGuard>,
+                              req: HttpRequest) -> impl Responder {
         info!("serving index request");
-        let app_state = data.app_state.lock().await;
-        app_state.as_ref().unwrap().index().await
+        let identity = req.extensions().get::<Identity>().cloned();
+        let page_view = data.app_state.lock().await.as_ref().unwrap().page_view().await;
+        match templates.render(&page_view, identity.as_ref(), &csrf.token(&req)) {
+            Ok(html) => HttpResponse::Ok().content_type("text/html; charset=utf-8").body(html),
+            Err(err) => {
+                error!("unable to render page: {}", err);
+                error_response(ErrorKind::Internal, err.to_string())
+            }
+        }
     }
 
-    #[delete("/")]
-    pub(crate) async fn reset(data: web::Data<AppData>) -> impl Responder {
-        info!("serving reset request");
-        let mut app_state = data.app_state.lock().await;
-        let (new_app_state, res) = app_state.take().unwrap().reset().await;
-        *app_state = Some(new_app_state);
-        res
+    #[get("/login")]
+    pub(crate) async fn login_page(authenticator: web::Data<Authenticator>, templates: web::Data<TemplateRegistry>) -> impl Responder {
+        info!("serving login page");
+        login_response(&templates, &LoginContext { has_users: authenticator.has_users(), failed: false })
     }
 
-    #[get("/status")]
-    pub(crate) async fn status(data: web::Data<AppData>) -> impl Responder {
-        info!("serving status request");
-        let app_state = data.app_state.lock().await;
-        app_state.as_ref().unwrap().status().await
+    #[post("/login")]
+    pub(crate) async fn login(credentials: web::Form<Credentials>, authenticator: web::Data<Authenticator>,
+                              templates: web::Data<TemplateRegistry>, req: HttpRequest) -> impl Responder {
+        info!("serving login");
+        let has_users = authenticator.has_users();
+        let secure = req.connection_info().scheme() == "https";
+        match auth::login(authenticator, credentials.into_inner()).await {
+            Ok(token_info) => HttpResponse::SeeOther()
+                .header(header::LOCATION, "/")
+                .cookie(auth::session_cookie(&token_info.token, secure))
+                .finish(),
+            Err(res) if res.status() == StatusCode::UNAUTHORIZED => {
+                let mut res = login_response(&templates, &LoginContext { has_users, failed: true });
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+                res
+            }
+            Err(res) => res,
+        }
     }
 
-    #[post("/page_form")]
-    pub(crate) async fn post_page_form(page_form: web::Form<PageForm>, data: web::Data<AppData>) -> impl Responder {
+    fn login_response(templates: &TemplateRegistry, context: &LoginContext) -> HttpResponse {
+        match templates.render_login(context) {
+            Ok(html) => HttpResponse::Ok().content_type("text/html; charset=utf-8").body(html),
+            Err(err) => {
+                error!("unable to render login page: {}", err);
+                error_response(ErrorKind::Internal, err.to_string())
+            }
+        }
+    }
+
+    #[post("/logout")]
+    pub(crate) async fn logout(form: web::Form<CsrfForm>, authenticator: web::Data<Authenticator>, csrf: web::Data<CsrfGuard>,
+                               req: HttpRequest) -> impl Responder {
+        info!("serving logout");
+        if let Err(res) = csrf.verify(&req, &form.csrf_token) {
+            return res;
+        }
+        if let Some(token) = auth::request_token(&req) {
+            authenticator.end_session(&token);
+        }
+        HttpResponse::SeeOther()
+            .header(header::LOCATION, auth::LOGIN_PATH)
+            .del_cookie(&auth::session_cookie("", false))
+            .finish()
+    }
+
+    #[get("/static/{name}")]
+    pub(crate) async fn static_asset(assets: web::Data<StaticAssets>, web::Path(name): web::Path<String>) -> impl Responder {
+        assets.response(&name, None).await
+    }
+
+    #[get("/static/{hash}/{name}")]
+    pub(crate) async fn hashed_static_asset(assets: web::Data<StaticAssets>,
+                                            web::Path((hash, name)): web::Path<(String, String)>) -> impl Responder {
+        assets.response(&name, Some(&hash)).await
+    }
+
+    pub(crate) async fn post_page_form(post: web::Form<PageFormPost>, data: web::Data<AppData>, csrf: web::Data<CsrfGuard>,
+                                       req: HttpRequest) -> impl Responder {
         info!("serving page_form post request");
+        let post = post.into_inner();
+        if let Err(res) = csrf.verify(&req, &post.csrf_token) {
+            return res;
+        }
         let mut app_state = data.app_state.lock().await;
         let (new_app_state, res) = app_state.take().unwrap()
-            .post_page_form(page_form.0).await;
+            .post_page_form(post.form).await;
         *app_state = Some(new_app_state);
         res
     }
 
-    #[get("/media_content")]
-    pub(crate) async fn get_media_content(data: web::Data<AppData>) -> impl Responder {
-        info!("serving media_content index");
-        let app_state = data.app_state.lock().await;
-        app_state.as_ref().unwrap().get_content().await
+    pub(crate) async fn server_info(data: web::Data<AppData>, query: web::Query<ServerUrlQuery>) -> impl Responder {
+        info!("serving server info request");
+        let client = match ScanEdClient::new(&query.url, data.config.timeouts) {
+            Ok(client) => client,
+            Err(err) => return server_error_response(&err),
+        };
+        match client.handshake().await {
+            Ok(server_info) => HttpResponse::Ok().json(server_info),
+            Err(err) => server_error_response(&err),
+        }
     }
 
-    #[get("/media_content/{image_name}")]
-    pub(crate) async fn get_specific_media_content(data: web::Data<AppData>, req: HttpRequest) -> impl Responder {
-        info!("serving specific media_content");
-        let app_state = data.app_state.lock().await;
-        match req.match_info().get("image_name"){
-            None => {HttpResponse::BadRequest().finish()}
-            Some(image_name) => {
-                app_state.as_ref().unwrap().get_specific_content(image_name).await
+    pub(crate) async fn server_check(data: web::Data<AppData>, query: web::Query<ServerUrlQuery>) -> impl Responder {
+        info!("serving server check request");
+        match ScanEdClient::new(&query.url, data.config.timeouts) {
+            Ok(client) => HttpResponse::Ok().json(health::check_connection(&client).await),
+            Err(err) => server_error_response(&err),
+        }
+    }
+
+    pub(crate) async fn server_health(data: web::Data<AppData>, query: web::Query<HealthMonitorQuery>) -> impl Responder {
+        info!("serving server health monitor");
+        let interval = Duration::from_secs(query.interval.unwrap_or(health::DEFAULT_HEALTH_INTERVAL).max(1));
+        match ScanEdClient::new(&query.url, data.config.timeouts) {
+            Ok(client) => HttpResponse::Ok()
+                .header("Content-Type", "text/event-stream")
+                .header("Cache-Control", "no-cache")
+                .streaming(health::health_stream(client, interval)),
+            Err(err) => server_error_response(&err),
+        }
+    }
+
+    pub(crate) async fn server_discover(data: web::Data<AppData>) -> impl Responder {
+        info!("serving server discovery request");
+        match discovery::discover(data.config.discovery, data.config.timeouts).await {
+            Ok(servers) => HttpResponse::Ok().json(servers),
+            Err(err) => error_response(ErrorKind::Internal, err.to_string()),
+        }
+    }
+
+    // stream of the given server, or of the server of the current Auftrag
+    async fn resolve_stream_url(data: &AppData, query: &LiveStreamQuery) -> Result<reqwest::Url, HttpResponse> {
+        match &query.url {
+            Some(url) => server_com::parse_base_url(url)
+                .map(|server_url| livestream::stream_url(&server_url))
+                .map_err(|err| server_error_response(&err)),
+            None => data.app_state.lock().await.as_ref().unwrap().livestream_url()
+                .ok_or_else(|| error_response(ErrorKind::NotFound, "no ScanEd server configured")),
+        }
+    }
+
+    pub(crate) async fn get_livestream(data: web::Data<AppData>, query: web::Query<LiveStreamQuery>) -> impl Responder {
+        info!("serving livestream");
+        let stream_url = match resolve_stream_url(&data, &query).await {
+            Ok(stream_url) => stream_url,
+            Err(res) => return res,
+        };
+        let receiver = data.livestream.subscribe(stream_url);
+        HttpResponse::Ok()
+            .header("Content-Type", format!("multipart/x-mixed-replace; boundary={}", livestream::BOUNDARY))
+            .header("Cache-Control", "no-cache")
+            .streaming(livestream::viewer_stream(receiver))
+    }
+
+    pub(crate) async fn take_snapshot(data: web::Data<AppData>, query: web::Query<LiveStreamQuery>) -> impl Responder {
+        info!("serving snapshot request");
+        let stream_url = match resolve_stream_url(&data, &query).await {
+            Ok(stream_url) => stream_url,
+            Err(res) => return res,
+        };
+        match snapshot::take_snapshot(&data.livestream, stream_url).await {
+            Ok(preview) => {
+                let report = preview.report.clone();
+                *data.preview.lock().await = Some(preview);
+                HttpResponse::Ok().json(report)
             }
+            Err(err) => error_response(ErrorKind::ServerError, err),
+        }
+    }
+
+    pub(crate) async fn get_snapshot(data: web::Data<AppData>) -> impl Responder {
+        info!("serving snapshot report");
+        match data.preview.lock().await.as_ref() {
+            Some(preview) => HttpResponse::Ok().json(&preview.report),
+            None => error_response(ErrorKind::NotFound, "no snapshot taken"),
+        }
+    }
+
+    pub(crate) async fn get_snapshot_image(data: web::Data<AppData>) -> impl Responder {
+        info!("serving snapshot image");
+        match data.preview.lock().await.as_ref() {
+            Some(preview) => HttpResponse::Ok()
+                .header("Content-Type", "image/jpeg")
+                .header("Cache-Control", "no-cache")
+                .body(preview.jpeg.clone()),
+            None => error_response(ErrorKind::NotFound, "no snapshot taken"),
         }
     }
 
-    #[get("/ws_notification")]
     pub(crate) async fn ws_notification(req: HttpRequest, stream: web::Payload, data: web::Data<AppData>) -> impl Responder {
         info!("serving ws_notification");
         let app_state = data.app_state.lock().await;
@@ -78,31 +342,86 @@ mod endpoints {
 
 struct AppData {
     app_state: Mutex<Option<Box<dyn AppState + Send>>>,
+    config: Config,
+    livestream: LiveStreamHub,
+    preview: Mutex<Option<Preview>>,
 }
 
 #[actix_web::main]
 async fn main() {
+    let mut config = Config::from_args();
+    // the subcommands print their own progress, only problems are logged
+    env_logger::Builder::from_env(env_logger::Env::default()
+        .default_filter_or(if config.command.is_some() { "warn" } else { "info" })).init();
+    if let Some(command) = config.command.take() {
+        std::process::exit(cli::run(command, config.scan_settings()).await);
+    }
+
+    let html_source = config.html.clone();
+    let assets = web::Data::new(StaticAssets::new(html_source.clone()));
+    let asset_urls = assets.urls();
+    // templates are compiled per worker, checked here once so a broken template stops the start
+    if let Err(err) = TemplateRegistry::new(html_source.clone(), asset_urls.clone()) {
+        panic!("{}", err);
+    }
+    let authenticator = match Authenticator::new(&config.auth) {
+        Ok(authenticator) => web::Data::new(authenticator),
+        Err(err) => panic!("{}", err),
+    };
+    if !authenticator.is_enabled() {
+        warn!("authentication is off, everyone in the network may control the client");
+    }
+    let https = config.tls.as_ref().map(|tls_config| match tls::server_config(&tls_config.source) {
+        Ok(server_config) => (tls_config.https_port, server_config),
+        Err(err) => panic!("{}", err),
+    });
+    let https_port = https.as_ref().map(|(https_port, _)| *https_port);
+    let csrf_guard = web::Data::new(CsrfGuard::new());
     let app_data = web::Data::new(AppData {
-        app_state: Mutex::new(Some(Box::new(app_state::Start {}))),
+        app_state: Mutex::new(Some(Box::new(app_state::Start::new(config.scan_settings())))),
+        livestream: LiveStreamHub::new(config.timeouts.connect),
+        preview: Mutex::new(None),
+        config,
     });
 
-    env_logger::Builder::from_env(env_logger::Env::default()
-        .default_filter_or("info")).init();
-
     info!("starting client");
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
+            .data(TemplateRegistry::new(html_source.clone(), asset_urls.clone()).expect("templates were checked at startup"))
+            .wrap_fn(|req, srv| match auth::authorize(&req) {
+                Ok(()) => Either::Left(srv.call(req)),
+                Err(res) => Either::Right(future::ok(req.into_response(res))),
+            })
+            .wrap_fn(|req, srv| match csrf::check_origin(&req) {
+                Ok(()) => Either::Left(srv.call(req)),
+                Err(res) => Either::Right(future::ok(req.into_response(res))),
+            })
+            // registered last, so it runs before the authentication
+            .wrap_fn(move |req, srv| match https_port.and_then(|https_port| tls::https_redirect(&req, https_port)) {
+                None => Either::Left(srv.call(req)),
+                Some(res) => Either::Right(future::ok(req.into_response(res))),
+            })
             .service(endpoints::index)
-            .service(endpoints::status)
-            .service(endpoints::post_page_form)
-            .service(endpoints::get_media_content)
-            .service(endpoints::get_specific_media_content)
-            .service(endpoints::ws_notification)
-            .service(endpoints::reset)
-            .service(actix_files::Files::new("/static", "html/static"))
+            .service(endpoints::login_page)
+            .service(endpoints::login)
+            .service(endpoints::logout)
+            .service(endpoints::static_asset)
+            .service(endpoints::hashed_static_asset)
+            .configure(endpoints::configure)
+            .configure(api::configure)
             .app_data(app_data.clone())
+            .app_data(assets.clone())
+            .app_data(authenticator.clone())
+            .app_data(csrf_guard.clone())
     }).bind(SocketAddr::from_str("0.0.0.0:8080").unwrap())
-        .unwrap()
-        .run().await.unwrap();
+        .unwrap();
+    let server = match https {
+        Some((https_port, server_config)) => {
+            info!("serving https on port {}, http redirects to it", https_port);
+            server.bind_rustls(SocketAddr::from((Ipv4Addr::UNSPECIFIED, https_port)), server_config).unwrap()
+        }
+        None => server,
+    };
+    server.run().await.unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,35 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Cancels the tasks of a scan, every clone observes the same cancellation.
+#[derive(Clone)]
+pub struct CancellationToken {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        let (sender, receiver) = watch::channel(false);
+        CancellationToken { sender: Arc::new(sender), receiver }
+    }
+
+    pub fn cancel(&self) {
+        // fails only if no token is left, so nobody can observe it anyway
+        let _ = self.sender.broadcast(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Completes once the token was cancelled.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow() {
+            if receiver.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use crate::photogrammetry::image_handling::ImageStore;
+use crate::photogrammetry::paths;
+use crate::photogrammetry::cancellation::CancellationToken;
+use crate::server_com::{com_model, ScanEdClient, ServerError};
+use actix_web::rt::time::delay_for;
+use log::{info, warn, error};
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::JoinHandle;
+
+pub const DEFAULT_CONCURRENCY: usize = 4;
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const MAX_RETRY_DELAY: u64 = 30; // in seconds
+// throughput is averaged over the transfers of this many seconds
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+const IDLE_CHECK_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug)]
+pub struct DownloadConfig {
+    // images downloaded from the server at the same time
+    pub concurrency: usize,
+    pub max_attempts: u32,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        DownloadConfig { concurrency: DEFAULT_CONCURRENCY, max_attempts: DEFAULT_MAX_ATTEMPTS }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub enum DownloadState {
+    Queued,
+    Downloading,
+    Retrying,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct ImageDownload {
+    pub path: String,
+    pub name: String,
+    pub state: DownloadState,
+    pub attempts: u32,
+    pub bytes_received: u64,
+    pub last_error: Option<String>,
+}
+
+impl ImageDownload {
+    fn in_progress(&self) -> bool {
+        matches!(self.state, DownloadState::Queued | DownloadState::Downloading | DownloadState::Retrying)
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct DownloadStats {
+    pub queued: usize,
+    pub active: usize,
+    pub retrying: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub bytes_per_second: u64,
+    // every image which was not downloaded successfully yet
+    pub pending: Vec<ImageDownload>,
+}
+
+pub struct DownloadRequest {
+    pub aufnahme: com_model::Aufnahme,
+    // name of the image a recaptured image replaces
+    pub replaces: Option<String>,
+}
+
+#[derive(Error, Debug)]
+enum DownloadError {
+    #[error(transparent)]
+    Server(#[from] ServerError),
+    #[error("unable to write image: {0}")]
+    Io(#[from] tokio::io::Error),
+}
+
+/// Downloads images from the server with a limited number of simultaneous transfers.
+/// Interrupted transfers are kept in the partial folder and resumed on the next attempt.
+pub struct DownloadScheduler {
+    client: ScanEdClient,
+    image_store: Arc<ImageStore>,
+    config: DownloadConfig,
+    cancellation: CancellationToken,
+    slots: Arc<Semaphore>,
+    downloads: Mutex<HashMap<String, ImageDownload>>,
+    // bytes received in the last seconds
+    transfers: Mutex<VecDeque<(Instant, usize)>>,
+}
+
+impl DownloadScheduler {
+    pub fn new(client: ScanEdClient,
+               image_store: Arc<ImageStore>,
+               config: DownloadConfig,
+               cancellation: CancellationToken) -> DownloadScheduler {
+        DownloadScheduler {
+            client,
+            image_store,
+            config,
+            cancellation,
+            slots: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            downloads: Mutex::new(HashMap::new()),
+            transfers: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queues the download of an image, the request is handed back if the image is
+    /// already queued, downloading, stored or given up. Push and poll announce the same
+    /// images, the id decides here under one lock. Only a recapture downloads an image
+    /// again, with all attempts. The task returns whether the image was stored.
+    pub async fn enqueue(self: &Arc<Self>, request: DownloadRequest) -> Result<JoinHandle<bool>, DownloadRequest> {
+        {
+            let mut downloads = self.downloads.lock().await;
+            let attempts = match downloads.get(&request.aufnahme.id) {
+                Some(download) if download.in_progress() => return Err(request),
+                // a recapture may come back under the path of the image it replaces, it is a new image
+                Some(_) if request.replaces.is_some() => 0,
+                // announcing a failed image again would retry it endlessly
+                Some(download) if matches!(download.state, DownloadState::Completed | DownloadState::Failed) => {
+                    return Err(request);
+                }
+                Some(download) => download.attempts,
+                None => 0,
+            };
+            downloads.insert(request.aufnahme.id.clone(), ImageDownload {
+                path: request.aufnahme.server_path.clone(),
+                name: request.aufnahme.local_name.clone(),
+                state: DownloadState::Queued,
+                attempts,
+                bytes_received: 0,
+                last_error: None,
+            });
+        }
+        let scheduler = Arc::clone(self);
+        Ok(tokio::spawn(async move {
+            let id = request.aufnahme.id.clone();
+            tokio::select! {
+                stored = scheduler.download(request) => stored,
+                _ = scheduler.cancellation.cancelled() => {
+                    scheduler.update(&id, |download| download.state = DownloadState::Cancelled).await;
+                    false
+                }
+            }
+        }))
+    }
+
+    pub async fn stats(&self) -> DownloadStats {
+        let downloads = self.downloads.lock().await;
+        let count = |state| downloads.values().filter(|download| download.state == state).count();
+        let mut pending = downloads.values()
+            .filter(|download| download.state != DownloadState::Completed)
+            .cloned()
+            .collect::<Vec<_>>();
+        pending.sort_by(|a, b| a.path.cmp(&b.path));
+        DownloadStats {
+            queued: count(DownloadState::Queued),
+            active: count(DownloadState::Downloading),
+            retrying: count(DownloadState::Retrying),
+            completed: count(DownloadState::Completed),
+            failed: count(DownloadState::Failed),
+            cancelled: count(DownloadState::Cancelled),
+            bytes_per_second: self.bytes_per_second().await,
+            pending,
+        }
+    }
+
+    pub async fn wait_idle(&self) {
+        while self.downloads.lock().await.values().any(ImageDownload::in_progress) {
+            delay_for(IDLE_CHECK_DELAY).await;
+        }
+    }
+
+    async fn download(&self, request: DownloadRequest) -> bool {
+        loop {
+            // the slot is given back while waiting for a retry
+            let slot = Arc::clone(&self.slots).acquire_owned().await;
+            let attempts = self.update(&request.aufnahme.id, |download| {
+                download.state = DownloadState::Downloading;
+                download.attempts += 1;
+            }).await;
+            let result = self.try_download(&request).await;
+            drop(slot);
+            let error = match result {
+                Ok(()) => {
+                    self.update(&request.aufnahme.id, |download| {
+                        download.state = DownloadState::Completed;
+                        download.last_error = None;
+                    }).await;
+                    return true;
+                }
+                Err(err) => err,
+            };
+            if let DownloadError::Server(ServerError::Status(reqwest::StatusCode::RANGE_NOT_SATISFIABLE)) = error {
+                // the partial file does not match the image on the server anymore
+                remove_partial(&request.aufnahme).await;
+            }
+            let give_up = attempts >= self.config.max_attempts;
+            self.update(&request.aufnahme.id, |download| {
+                download.state = if give_up { DownloadState::Failed } else { DownloadState::Retrying };
+                download.last_error = Some(error.to_string());
+            }).await;
+            if give_up {
+                error!("giving up downloading {} after {} attempts: {}", request.aufnahme.id, attempts, error);
+                return false;
+            }
+            warn!("download of {} failed, retrying: {}", request.aufnahme.id, error);
+            delay_for(retry_delay(attempts)).await;
+        }
+    }
+
+    async fn try_download(&self, request: &DownloadRequest) -> Result<(), DownloadError> {
+        let partial = partial_path(&request.aufnahme);
+        let offset = match tokio::fs::metadata(&partial).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        let mut download = match self.client.get_aufnahme(&request.aufnahme, offset).await {
+            Err(err @ ServerError::RangeMismatch(_, _)) => {
+                // appending would corrupt the image, start over with the whole image
+                warn!("restarting download of {}: {}", request.aufnahme.id, err);
+                remove_partial(&request.aufnahme).await;
+                self.client.get_aufnahme(&request.aufnahme, 0).await?
+            }
+            result => result?,
+        };
+        let mut file = if download.resumed {
+            info!("resuming download of {} at byte {}", request.aufnahme.id, offset);
+            tokio::fs::OpenOptions::new().append(true).open(&partial).await?
+        } else {
+            tokio::fs::File::create(&partial).await?
+        };
+        let mut bytes_received = if download.resumed { offset } else { 0 };
+        while let Some(chunk) = download.next_chunk().await? {
+            file.write_all(&chunk).await?;
+            bytes_received += chunk.len() as u64;
+            self.record_transfer(chunk.len()).await;
+            self.update(&request.aufnahme.id, |download| download.bytes_received = bytes_received).await;
+        }
+        file.flush().await?;
+
+        let image = tokio::fs::read(&partial).await?;
+        match &request.replaces {
+            Some(old_name) => self.image_store.replace_image(old_name, &request.aufnahme, &image).await?,
+            None => self.image_store.store_image(&request.aufnahme, &image).await?,
+        };
+        remove_partial(&request.aufnahme).await;
+        Ok(())
+    }
+
+    // returns the number of attempts after the update
+    async fn update<F: FnOnce(&mut ImageDownload)>(&self, path: &str, f: F) -> u32 {
+        let mut downloads = self.downloads.lock().await;
+        match downloads.get_mut(path) {
+            Some(download) => {
+                f(download);
+                download.attempts
+            }
+            None => 0,
+        }
+    }
+
+    async fn record_transfer(&self, bytes: usize) {
+        let mut transfers = self.transfers.lock().await;
+        let now = Instant::now();
+        transfers.push_back((now, bytes));
+        while transfers.front().is_some_and(|(at, _)| now.duration_since(*at) > THROUGHPUT_WINDOW) {
+            transfers.pop_front();
+        }
+    }
+
+    async fn bytes_per_second(&self) -> u64 {
+        let transfers = self.transfers.lock().await;
+        let now = Instant::now();
+        let bytes = transfers.iter()
+            .filter(|(at, _)| now.duration_since(*at) <= THROUGHPUT_WINDOW)
+            .map(|(_, bytes)| *bytes as u64)
+            .sum::<u64>();
+        bytes / THROUGHPUT_WINDOW.as_secs()
+    }
+}
+
+fn retry_delay(attempts: u32) -> Duration {
+    Duration::from_secs(2u64.saturating_pow(attempts).min(MAX_RETRY_DELAY))
+}
+
+fn partial_path(aufnahme: &com_model::Aufnahme) -> std::path::PathBuf {
+    paths::partial_folder().join(format!("{}.part", aufnahme.local_name))
+}
+
+async fn remove_partial(aufnahme: &com_model::Aufnahme) {
+    if let Err(err) = tokio::fs::remove_file(partial_path(aufnahme)).await {
+        warn!("unable to remove partial download of {}: {}", aufnahme.id, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_com::Timeouts;
+
+    #[tokio::test]
+    async fn failed_images_are_not_retried_endlessly() {
+        let _folder = paths::lock_test_folder().await;
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let client = ScanEdClient::new(&format!("http://127.0.0.1:{}/", port), Timeouts::default()).unwrap();
+        let image_store = Arc::new(ImageStore::new("failed downloads").await.unwrap());
+        let config = DownloadConfig { concurrency: 1, max_attempts: 1 };
+        let scheduler = Arc::new(DownloadScheduler::new(client, image_store, config, CancellationToken::new()));
+        let request = |replaces: Option<&str>| DownloadRequest {
+            aufnahme: com_model::Aufnahme::from_server_path("img_1.jpg").unwrap(),
+            replaces: replaces.map(str::to_string),
+        };
+
+        // the connection is refused, the only attempt fails
+        assert!(!scheduler.enqueue(request(None)).await.ok().unwrap().await.unwrap());
+        for _ in 0..3 {
+            assert!(scheduler.enqueue(request(None)).await.is_err());
+        }
+        let stats = scheduler.stats().await;
+        assert_eq!((stats.failed, stats.pending[0].attempts), (1, 1));
+
+        // a recapture starts over
+        assert!(!scheduler.enqueue(request(Some("img_1.jpg"))).await.ok().unwrap().await.unwrap());
+        assert_eq!(scheduler.stats().await.pending[0].attempts, 1);
+    }
+}
@@ -1,169 +1,782 @@
 use tokio::fs::{File};
 use tokio::io::{AsyncWriteExt, AsyncReadExt};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet, HashMap};
 use tokio::sync::Mutex;
 use std::ops::Deref;
-use crate::server_com;
+use crate::server_com::{ScanEdClient, ServerError};
+use crate::config::ScanSettings;
 use std::iter::FromIterator;
 use std::error::Error;
 use std::sync::Arc;
-use crate::web_interface::model::ImageAppStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::web_interface::model::{ImageAppStatus, ResetReport};
 use crate::web_interface::model::ws::{MyWs, Notification};
 use actix::Addr;
 use crate::server_com::com_model;
 use actix_web::rt::time::delay_for;
-use log::{info, error};
+use log::{info, error, warn};
 use crate::photogrammetry::paths;
+use crate::photogrammetry::cancellation::CancellationToken;
+use tokio::task::JoinHandle;
+use tokio::sync::watch;
+use crate::photogrammetry::downloads::{DownloadScheduler, DownloadRequest, DownloadConfig, DownloadStats};
+use crate::photogrammetry::thumbnails::{self, ThumbnailSize};
+use crate::photogrammetry::metadata::ImageMetadata;
+use crate::photogrammetry::quality::{self, ImageQuality, QualityReport};
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ring::rand::{SecureRandom, SystemRandom};
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct ImageVersion {
+    pub version: usize,
+    pub server_path: String,
+    pub stored_at: u64, // unix timestamp in seconds
+}
+
+/// Exclusion list as written to disk, together with the session it belongs to.
+#[derive(Serialize, Deserialize, Default)]
+struct ExclusionFile {
+    session: String,
+    excluded: BTreeSet<String>,
+}
 
 pub struct ImageStore {
+    // the session the exclusion list is persisted for
+    session: String,
     image_list: Mutex<HashSet<String>>,
+    metadata: Mutex<HashMap<String, ImageMetadata>>,
+    quality: Mutex<HashMap<String, ImageQuality>>,
+    excluded: Mutex<HashSet<String>>,
+    versions: Mutex<HashMap<String, Vec<ImageVersion>>>,
+    // server images which were stored under the name of the image they replaced
+    aliases: Mutex<HashSet<String>>,
 }
 
 impl ImageStore {
-    pub async fn new() -> tokio::io::Result<ImageStore> {
+    /// Starts with empty folders. Exclusions are only kept when the same session is opened
+    /// again, images of that session which are downloaded again stay excluded.
+    pub async fn new(session: &str) -> tokio::io::Result<ImageStore> {
+        let excluded = match tokio::fs::read(paths::exclusion_file()).await {
+            Ok(content) => serde_json::from_slice::<ExclusionFile>(&content)
+                .ok()
+                .filter(|file| file.session == session)
+                .map(|file| file.excluded.into_iter().collect())
+                .unwrap_or_default(),
+            Err(_) => HashSet::new(),
+        };
         init_dir().await?;
-        Ok(ImageStore { image_list: Mutex::new(HashSet::new()) })
+        if !excluded.is_empty() {
+            info!("keeping {} excluded images of the previous session", excluded.len());
+        }
+        let image_store = ImageStore {
+            session: session.to_string(),
+            image_list: Mutex::new(HashSet::new()),
+            metadata: Mutex::new(HashMap::new()),
+            quality: Mutex::new(HashMap::new()),
+            excluded: Mutex::new(excluded),
+            versions: Mutex::new(HashMap::new()),
+            aliases: Mutex::new(HashSet::new()),
+        };
+        image_store.write_exclusions(&*image_store.excluded.lock().await).await?;
+        Ok(image_store)
     }
 
-    pub async fn store_image(&self, image_path: &str, image: &Vec<u8>) -> Result<(), tokio::io::Error> {
+    pub async fn store_image(&self, aufnahme: &com_model::Aufnahme, image: &[u8]) -> Result<(), tokio::io::Error> {
+        let image_name = &aufnahme.local_name;
+        let index = index_image(image_name, image, aufnahme.capture_position()).await?;
+        let excluded = self.excluded.lock().await.contains(image_name);
+        save_image(image_name, image, excluded).await?;
+
         let mut image_list = self.image_list.lock().await;
-        let image_name = image_path.split("/").last().unwrap();
-        save_image(image_name, &image).await?;
+        self.insert_index(image_name, index).await;
+        self.add_version(image_name, &aufnahme.server_path).await;
         image_list.insert(image_name.to_string());
         Ok(())
     }
 
+    /// Stores a recaptured image under the name of the image it replaces, the previous
+    /// version is moved to the history folder.
+    pub async fn replace_image(&self, name: &str, aufnahme: &com_model::Aufnahme, image: &[u8]) -> Result<(), tokio::io::Error> {
+        // the replacement keeps the capture position of the original shot
+        let captured_at = self.metadata.lock().await.get(name)
+            .and_then(|metadata| Some(com_model::ServerStatus {
+                runde: metadata.round?,
+                aufnahme: metadata.shot?,
+            }));
+        let index = index_image(name, image, captured_at).await?;
+
+        let _image_list = self.image_list.lock().await;
+        let excluded = self.excluded.lock().await.contains(name);
+        let version = self.versions.lock().await.get(name).map_or(0, |versions| versions.len());
+        tokio::fs::rename(
+            image_path(name, excluded),
+            paths::history_folder().join(format!("v{}_{}", version, name)),
+        ).await?;
+        tokio::fs::write(image_path(name, excluded), image).await?;
+        self.insert_index(name, index).await;
+        self.add_version(name, &aufnahme.server_path).await;
+        self.aliases.lock().await.insert(aufnahme.local_name.clone());
+        Ok(())
+    }
+
+    async fn insert_index(&self, name: &str, index: ImageIndex) {
+        self.metadata.lock().await.insert(name.to_string(), index.metadata);
+        if let Some(image_quality) = index.quality {
+            self.quality.lock().await.insert(name.to_string(), image_quality);
+        }
+    }
+
+    async fn add_version(&self, name: &str, server_path: &str) {
+        let mut versions = self.versions.lock().await;
+        let versions = versions.entry(name.to_string()).or_insert_with(Vec::new);
+        versions.push(ImageVersion {
+            version: versions.len() + 1,
+            server_path: server_path.to_string(),
+            stored_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+        });
+    }
+
+    pub async fn get_versions(&self, name: &str) -> Option<Vec<ImageVersion>> {
+        self.versions.lock().await.get(name).cloned()
+    }
+
+    pub async fn image_count(&self) -> usize {
+        self.image_list.lock().await.len()
+    }
+
+    // local and aliased names of every image which was already downloaded
+    async fn known_names(&self) -> HashSet<String> {
+        let image_list = self.image_list.lock().await;
+        image_list.union(&*self.aliases.lock().await).cloned().collect()
+    }
+
+    // images which are used for the reconstruction
     pub async fn get_image_list(&self) -> Vec<String> {
         let image_list = self.image_list.lock().await;
-        Vec::from_iter(image_list.deref().clone().into_iter())
+        let excluded = self.excluded.lock().await;
+        Vec::from_iter(image_list.difference(&excluded).cloned())
+    }
+
+    // only images which were downloaded, the list of the session may name more
+    pub async fn get_excluded_list(&self) -> Vec<String> {
+        let image_list = self.image_list.lock().await;
+        let excluded = self.excluded.lock().await;
+        Vec::from_iter(excluded.intersection(&image_list).cloned())
     }
 
     pub async fn get_image(&self, name: &String) -> Result<Vec<u8>, Option<tokio::io::Error>> {
         let image_list = self.image_list.lock().await;
         if image_list.contains(name) {
-            Ok(read_image(name).await.map_err(|err| Some(err))?)
+            let excluded = self.excluded.lock().await.contains(name);
+            Ok(read_image(name, excluded).await.map_err(Some)?)
         } else {
             Err(None)
         }
     }
+
+    /// Moves the image out of (or back into) the OpenDroneMap input folder and persists
+    /// the exclusion list next to the session images.
+    pub async fn set_excluded(&self, name: &str, exclude: bool) -> Result<(), Option<tokio::io::Error>> {
+        let image_list = self.image_list.lock().await;
+        if !image_list.contains(name) {
+            return Err(None);
+        }
+        let mut excluded = self.excluded.lock().await;
+        if excluded.contains(name) == exclude {
+            return Ok(());
+        }
+        tokio::fs::rename(image_path(name, !exclude), image_path(name, exclude)).await
+            .map_err(Some)?;
+        if exclude {
+            excluded.insert(name.to_string());
+        } else {
+            excluded.remove(name);
+        }
+        self.write_exclusions(&excluded).await.map_err(Some)
+    }
+
+    async fn write_exclusions(&self, excluded: &HashSet<String>) -> tokio::io::Result<()> {
+        let exclusion_file = ExclusionFile {
+            session: self.session.clone(),
+            excluded: excluded.iter().cloned().collect(),
+        };
+        let content = serde_json::to_vec(&exclusion_file)
+            .map_err(|err| tokio::io::Error::new(tokio::io::ErrorKind::InvalidData, err))?;
+        tokio::fs::write(paths::exclusion_file(), content).await
+    }
+
+    pub async fn get_metadata_list(&self) -> Vec<ImageMetadata> {
+        let mut metadata_list = self.metadata.lock().await.values().cloned().collect::<Vec<_>>();
+        metadata_list.sort_by(|a, b| a.name.cmp(&b.name));
+        metadata_list
+    }
+
+    pub async fn get_metadata(&self, name: &str) -> Option<ImageMetadata> {
+        self.metadata.lock().await.get(name).cloned()
+    }
+
+    pub async fn get_quality_report(&self) -> QualityReport {
+        let metadata = self.metadata.lock().await;
+        let excluded = self.excluded.lock().await;
+        let mut images = self.quality.lock().await.iter()
+            .filter(|(name, _)| !excluded.contains(*name))
+            .map(|(name, image_quality)| (name.clone(), image_quality.clone()))
+            .collect::<Vec<_>>();
+        // compare images in the order they were captured
+        images.sort_by_key(|(name, _)| {
+            let capture_position = metadata.get(name).map(|metadata| (metadata.round, metadata.shot));
+            (capture_position, name.clone())
+        });
+        quality::report(images)
+    }
+
+    pub async fn get_thumbnail(&self, name: &String, size: ThumbnailSize) -> Result<Vec<u8>, Option<tokio::io::Error>> {
+        let image_list = self.image_list.lock().await;
+        if image_list.contains(name) {
+            Ok(thumbnails::read_thumbnail(name, size).await.map_err(Some)?)
+        } else {
+            Err(None)
+        }
+    }
+}
+
+// what is derived from an image when it is stored
+struct ImageIndex {
+    metadata: ImageMetadata,
+    quality: Option<ImageQuality>,
 }
 
-async fn save_image(name: &str, img: &Vec<u8>) -> Result<(), tokio::io::Error> {
-    let mut file = File::create(paths::image_folder().join(name)).await?;
+/// Decodes the image once to read its metadata, cache the thumbnails and analyse the quality.
+async fn index_image(name: &str, image: &[u8], captured_at: Option<com_model::ServerStatus>) -> tokio::io::Result<ImageIndex> {
+    let name = name.to_string();
+    let image = image.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let metadata = ImageMetadata::read(&name, &image, captured_at.as_ref());
+        let decoded = match image::load_from_memory(&image) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                warn!("unable to decode {}: {}", name, err);
+                return ImageIndex { metadata, quality: None };
+            }
+        };
+        if let Err(err) = thumbnails::write_thumbnails(&name, &decoded) {
+            warn!("unable to generate thumbnails for {}: {}", name, err);
+        }
+        ImageIndex { metadata, quality: Some(ImageQuality::of_image(&decoded)) }
+    }).await.map_err(tokio::io::Error::other)
+}
+
+async fn save_image(name: &str, img: &[u8], excluded: bool) -> Result<(), tokio::io::Error> {
+    let mut file = File::create(image_path(name, excluded)).await?;
     file.write_all(img).await?;
     Ok(())
 }
 
-async fn read_image(name: &str) -> tokio::io::Result<Vec<u8>> {
-    let mut file = File::open(paths::image_folder().join(name)).await?;
+fn image_path(name: &str, excluded: bool) -> PathBuf {
+    if excluded {
+        paths::excluded_folder().join(name)
+    } else {
+        paths::image_folder().join(name)
+    }
+}
+
+async fn read_image(name: &str, excluded: bool) -> tokio::io::Result<Vec<u8>> {
+    let mut file = File::open(image_path(name, excluded)).await?;
     let mut buf = Vec::new();
     file.read_to_end(&mut buf).await?;
     Ok(buf)
 }
 
+/// Random id of a scan session, the exclusion list is kept only for the same session.
+pub fn new_session_id() -> String {
+    let mut id = [0u8; 16];
+    SystemRandom::new().fill(&mut id).expect("the system random number generator failed");
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// removes everything of the previous scan but the exclusion list
 async fn init_dir() -> tokio::io::Result<()> {
     if paths::parent_folder().exists() {
-        tokio::fs::remove_dir_all(paths::parent_folder()).await?;
+        let mut entries = tokio::fs::read_dir(paths::parent_folder()).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path() == paths::exclusion_file() {
+                continue;
+            }
+            if entry.file_type().await?.is_dir() {
+                tokio::fs::remove_dir_all(entry.path()).await?;
+            } else {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
     }
     tokio::fs::create_dir_all(paths::image_folder()).await?;
+    tokio::fs::create_dir_all(paths::excluded_folder()).await?;
+    tokio::fs::create_dir_all(paths::history_folder()).await?;
+    tokio::fs::create_dir_all(paths::partial_folder()).await?;
+    thumbnails::init_dir().await?;
     Ok(())
 }
 
+fn notifie_ws(notification_handle: Arc<std::sync::Mutex<Option<Addr<MyWs>>>>) {
+    tokio::task::spawn_blocking(move || {
+        let notification_handle_option = notification_handle
+            .lock()
+            .unwrap();
+        if let Some(notification_handle) = notification_handle_option.deref() {
+            notification_handle.do_send(Notification("new image".to_string()));
+        } else {
+            info!("websocket not available update message was therefore not send")
+        }
+    });
+}
+
 pub const POLL_DELAY: u64 = 3; // in seconds
+// polling only catches missed events while images are pushed
+pub const PUSH_POLL_DELAY: u64 = 30; // in seconds
+const PUSH_RECONNECT_DELAY: u64 = 5; // in seconds
+// time the server gets to list the last images after it reported the Auftrag complete
+const RECONCILE_DELAY: u64 = 2; // in seconds
+pub const DEFAULT_SCAN_TIMEOUT: u64 = 60 * 60; // in seconds
+// time the server gets to return to idle after the Auftrag was cancelled
+const IDLE_CONFIRM_TIMEOUT: u64 = 10; // in seconds
+
+/// Image waiting for the recapture of its shot.
+struct PendingRecapture {
+    image_name: String,
+    position: com_model::ServerStatus,
+    // path the server announced for the new image, if it did
+    id: Option<String>,
+}
+
+impl PendingRecapture {
+    fn matches(&self, aufnahme: &com_model::Aufnahme) -> bool {
+        self.id.as_ref() == Some(&aufnahme.id) || aufnahme.capture_position().as_ref() == Some(&self.position)
+    }
+}
+
+/// Conditions under which the Auftrag counts as done, any of them is sufficient.
+pub struct Completion {
+    pub auftrag: com_model::Auftrag,
+    pub target_server_status: com_model::ServerStatus,
+    pub expected_images: usize,
+    // polling gives up after this time, even if images are missing
+    pub timeout: Duration,
+}
+
+impl Completion {
+    /// None for an Auftrag without any images.
+    pub fn for_auftrag(auftrag: &com_model::Auftrag, timeout: Duration) -> Option<Completion> {
+        let expected_images = auftrag.expected_images();
+        if expected_images == 0 {
+            return None;
+        }
+        Some(Completion {
+            auftrag: auftrag.clone(),
+            target_server_status: auftrag.target_status()?,
+            expected_images,
+            timeout,
+        })
+    }
+}
+
+// marks a pause or resume as running, until it is dropped with its request
+struct PauseChange<'a>(&'a AtomicBool);
+
+impl<'a> PauseChange<'a> {
+    fn begin(changing: &'a AtomicBool) -> Option<PauseChange<'a>> {
+        changing.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).ok()?;
+        Some(PauseChange(changing))
+    }
+}
+
+impl Drop for PauseChange<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
 
 pub struct ImageDownloader {
-    url: String,
-    target_server_status: com_model::ServerStatus,
+    client: ScanEdClient,
+    server_info: com_model::ServerInfo,
+    completion: Completion,
+    started_at: Instant,
+    // the server left the idle state, so returning to it means it is done
+    progress_seen: Mutex<bool>,
+    finishing: Mutex<bool>,
     notification_handle: Arc<std::sync::Mutex<Option<Addr<MyWs>>>>,
     image_store: Arc<ImageStore>,
+    scheduler: Arc<DownloadScheduler>,
     app_image_status: Arc<Mutex<ImageAppStatus>>,
-    reset: Mutex<bool>,
+    cancellation: CancellationToken,
+    // set while the Auftrag is paused, the polling loop waits for it to be cleared
+    paused_at: Mutex<Option<Instant>>,
+    // set while a pause or resume waits for the server, so no lock is held meanwhile
+    pause_changing: AtomicBool,
+    paused_for: Mutex<Duration>,
+    pause_sender: watch::Sender<bool>,
+    pause_receiver: watch::Receiver<bool>,
+    // polling and push tasks, awaited when the scan is cancelled
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    // images waiting for their recaptured replacement, matched by shot or announced path
+    pending_recaptures: Arc<Mutex<Vec<PendingRecapture>>>,
+    // shots the server named in its events, for images which are then downloaded after a poll
+    event_positions: Mutex<HashMap<String, com_model::ServerStatus>>,
+    // recaptured shots are listed after later shots, the index order then says nothing about the position
+    recaptured: Mutex<bool>,
+    polling: Mutex<bool>,
+    push_running: Mutex<bool>,
+    push_connected: Mutex<bool>,
 }
 
 impl ImageDownloader {
-    pub async fn new(url: String,
-                     target_server_status: com_model::ServerStatus,
-                     notification_handle: Arc<std::sync::Mutex<Option<Addr<MyWs>>>>) -> Result<ImageDownloader, Box<dyn Error + Send>> {
+    pub async fn new(client: ScanEdClient,
+                     server_info: com_model::ServerInfo,
+                     completion: Completion,
+                     session: &str,
+                     notification_handle: Arc<std::sync::Mutex<Option<Addr<MyWs>>>>,
+                     download_config: DownloadConfig) -> Result<ImageDownloader, Box<dyn Error + Send>> {
+        let image_store = Arc::new(
+            ImageStore::new(session)
+                .await
+                .map_err(|err| -> Box<dyn Error + Send> { Box::new(err)})?);
+        let cancellation = CancellationToken::new();
+        let (pause_sender, pause_receiver) = watch::channel(false);
+        let scheduler = Arc::new(DownloadScheduler::new(
+            client.clone(),
+            Arc::clone(&image_store),
+            download_config,
+            cancellation.clone()));
         Ok(ImageDownloader {
-            url,
-            target_server_status,
+            client,
+            server_info,
+            completion,
+            started_at: Instant::now(),
+            progress_seen: Mutex::new(false),
+            finishing: Mutex::new(false),
             notification_handle,
-            image_store: Arc::new(
-                ImageStore::new()
-                    .await
-                    .map_err(|err| -> Box<dyn Error + Send> { Box::new(err)})?),
+            image_store,
+            scheduler,
             app_image_status: Arc::new(Mutex::new(ImageAppStatus::Start)),
-            reset: Mutex::new(false),
+            cancellation,
+            paused_at: Mutex::new(None),
+            pause_changing: AtomicBool::new(false),
+            paused_for: Mutex::new(Duration::from_secs(0)),
+            pause_sender,
+            pause_receiver,
+            tasks: Mutex::new(Vec::new()),
+            pending_recaptures: Arc::new(Mutex::new(Vec::new())),
+            event_positions: Mutex::new(HashMap::new()),
+            recaptured: Mutex::new(false),
+            polling: Mutex::new(false),
+            push_running: Mutex::new(false),
+            push_connected: Mutex::new(false),
         })
     }
 
+    /// Sends the Auftrag to the server and starts downloading its images.
+    pub async fn start_auftrag(server_url: &str,
+                               rounds: Vec<i32>,
+                               settings: ScanSettings,
+                               notification_handle: Arc<std::sync::Mutex<Option<Addr<MyWs>>>>) -> Result<Arc<ImageDownloader>, Box<dyn Error + Send>> {
+        let auftrag = com_model::Auftrag::from_vec(rounds);
+        let completion = Completion::for_auftrag(&auftrag, settings.scan_timeout)
+            .ok_or_else(|| -> Box<dyn Error + Send> { Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Auftrag without images")) })?;
+        let client = ScanEdClient::new(server_url, settings.timeouts)
+            .map_err(|err| -> Box<dyn Error + Send> { Box::new(err) })?;
+        let server_info = client.handshake().await
+            .map_err(|err| -> Box<dyn Error + Send> { Box::new(err) })?;
+        client.post_auftrag(auftrag).await
+            .map_err(|err| -> Box<dyn Error + Send> { Box::new(err) })?;
+        // every Auftrag is a new session, exclusions of earlier scans do not apply
+        let image_downloader = Arc::new(ImageDownloader::new(
+            client,
+            server_info,
+            completion,
+            &new_session_id(),
+            notification_handle,
+            settings.downloads).await?);
+        Arc::clone(&image_downloader).start().await;
+        Ok(image_downloader)
+    }
+
     pub async fn get_status(&self) -> ImageAppStatus {
+        if self.paused_at.lock().await.is_some() {
+            return ImageAppStatus::Paused;
+        }
         self.app_image_status.lock().await.deref().clone()
     }
 
+    /// Pauses the Auftrag on the server and suspends polling, Err(None) if the Auftrag
+    /// is already paused, being paused or resumed, or done.
+    pub async fn pause(&self) -> Result<(), Option<ServerError>> {
+        if !self.server_info.supports(com_model::ServerInfo::PAUSE) {
+            return Err(Some(ServerError::ProtocolMismatch(com_model::ServerInfo::PAUSE.to_string())));
+        }
+        let _change = PauseChange::begin(&self.pause_changing).ok_or(None)?;
+        if self.paused_at.lock().await.is_some() || self.app_image_status.lock().await.is_done() {
+            return Err(None);
+        }
+        self.client.post_pause().await.map_err(Some)?;
+        *self.paused_at.lock().await = Some(Instant::now());
+        // fails only if the downloader was dropped meanwhile
+        let _ = self.pause_sender.broadcast(true);
+        info!("Auftrag paused");
+        self.notifie_ws().await;
+        Ok(())
+    }
+
+    /// Continues the paused Auftrag with the same image store, Err(None) if it is not paused
+    /// or being paused or resumed.
+    pub async fn resume(&self) -> Result<(), Option<ServerError>> {
+        let _change = PauseChange::begin(&self.pause_changing).ok_or(None)?;
+        let paused_since = self.paused_at.lock().await.ok_or(None)?;
+        self.client.post_fortsetzen().await.map_err(Some)?;
+        *self.paused_for.lock().await += paused_since.elapsed();
+        *self.paused_at.lock().await = None;
+        let _ = self.pause_sender.broadcast(false);
+        info!("Auftrag resumed");
+        self.notifie_ws().await;
+        Ok(())
+    }
+
+    async fn wait_while_paused(&self) {
+        let mut pause_receiver = self.pause_receiver.clone();
+        while *pause_receiver.borrow() {
+            if pause_receiver.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    // time the Auftrag was running, without pauses
+    async fn active_time(&self) -> Duration {
+        let paused_for = *self.paused_for.lock().await;
+        self.started_at.elapsed().checked_sub(paused_for).unwrap_or_default()
+    }
+
+    /// Starts the polling loop, unless it is still running. Servers which support events
+    /// additionally push new images, polling then only runs as a slow fallback.
     pub async fn start(self: Arc<Self>) {
-        let app_image_status = Arc::clone(&self.app_image_status);
-        tokio::spawn(async move {
-            while app_image_status.lock().await.deref().ne(&ImageAppStatus::Finished)
-                || *self.reset.lock().await {
-                if let Some(new_status) = self.get_new_status().await.expect("failed to get status") {
-                    *self.app_image_status.lock().await = new_status;
-                    self.download_images().await.expect("failed to download images");
-                    self.notifie_ws().await;
+        if self.server_info.supports(com_model::ServerInfo::EVENTS) {
+            Arc::clone(&self).start_push().await;
+        }
+        {
+            let mut polling = self.polling.lock().await;
+            if *polling {
+                return;
+            }
+            *polling = true;
+        }
+        let downloader = Arc::clone(&self);
+        let task = tokio::spawn(async move {
+            let cancellation = downloader.cancellation.clone();
+            tokio::select! {
+                _ = downloader.poll() => {}
+                _ = cancellation.cancelled() => info!("polling cancelled"),
+            }
+        });
+        self.tasks.lock().await.push(task);
+    }
+
+    async fn poll(&self) {
+        loop {
+            {
+                // checked under the lock, so a recapture requested meanwhile restarts the loop
+                let mut polling = self.polling.lock().await;
+                if !self.keep_polling().await {
+                    *polling = false;
+                    break;
+                }
+            }
+            self.wait_while_paused().await;
+            if self.active_time().await > self.completion.timeout
+                && !self.app_image_status.lock().await.is_done() {
+                warn!("Auftrag did not finish within {} seconds, giving up", self.completion.timeout.as_secs());
+                *self.app_image_status.lock().await = ImageAppStatus::TimedOut;
+                self.notifie_ws().await;
+                continue;
+            }
+            let new_status = match self.get_new_status().await {
+                Ok(new_status) => new_status,
+                Err(err) => {
+                    // the server may be temporarily unreachable, try again with the next poll
+                    error!("failed to get status: {}", err);
+                    delay_for(tokio::time::Duration::from_secs(POLL_DELAY)).await;
+                    continue;
                 }
-                delay_for(tokio::time::Duration::from_secs(POLL_DELAY)).await;
             };
+            let recapture_pending = !self.pending_recaptures.lock().await.is_empty();
+            if new_status.is_some() || recapture_pending {
+                if let Some(new_status) = new_status {
+                    self.set_status(new_status).await;
+                }
+                if let Err(err) = self.download_images().await {
+                    error!("failed to download images: {}", err);
+                }
+                self.notifie_ws().await;
+            }
+            let poll_delay = if *self.push_connected.lock().await { PUSH_POLL_DELAY } else { POLL_DELAY };
+            delay_for(tokio::time::Duration::from_secs(poll_delay)).await;
+        };
+    }
+
+    async fn start_push(self: Arc<Self>) {
+        {
+            let mut push_running = self.push_running.lock().await;
+            if *push_running {
+                return;
+            }
+            *push_running = true;
+        }
+        let downloader = Arc::clone(&self);
+        let task = tokio::spawn(async move {
+            let cancellation = downloader.cancellation.clone();
+            tokio::select! {
+                _ = downloader.receive_pushed() => {}
+                _ = cancellation.cancelled() => info!("push connection cancelled"),
+            }
         });
+        self.tasks.lock().await.push(task);
     }
 
-    async fn get_new_image_paths(&self) -> Result<Vec<String>, Box<dyn Error + Send>> {
-        let available_images = server_com::get_ready_image_list(&self.url).await
-            .map_err(|err| -> Box<dyn Error + Send> { Box::new(err) })?;
-        let old_images = self.image_store.image_list.lock().await;
-        Ok(available_images
-            .difference(&HashSet::from_iter(old_images.iter().map(|image_name| format!("/aufnahme/{}", image_name))))
-            .map(|image_name| image_name.clone())
-            .collect::<Vec<_>>())
+    async fn receive_pushed(self: &Arc<Self>) {
+        loop {
+            {
+                let mut push_running = self.push_running.lock().await;
+                if !self.keep_polling().await {
+                    *push_running = false;
+                    break;
+                }
+            }
+            match self.client.subscribe_events().await {
+                Ok(mut events) => {
+                    info!("receiving images via push");
+                    *self.push_connected.lock().await = true;
+                    while self.keep_polling().await {
+                        match events.next_event().await {
+                            Ok(Some(event)) => self.handle_event(event).await,
+                            Ok(None) => break,
+                            Err(err) => {
+                                warn!("push connection failed: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    *self.push_connected.lock().await = false;
+                    info!("push connection closed, falling back to polling");
+                }
+                Err(err) => warn!("unable to subscribe to server events: {}", err),
+            }
+            delay_for(tokio::time::Duration::from_secs(PUSH_RECONNECT_DELAY)).await;
+        }
     }
 
-    async fn download_images(&self) -> Result<(), Box<dyn Error + Send>> {
-        let new_images = self.get_new_image_paths().await?;
-        let mut task_handles = Vec::new();
-        for image_path in new_images {
-            let url = self.url.clone();
-            let image_store = Arc::clone(&self.image_store);
-            let t = tokio::spawn(async move {
-                //downlaod aufnahme from server
-                let image = match server_com::get_aufnahme(&url, &image_path).await {
-                    Ok(image) => image,
-                    Err(err) => {
-                        error!("Error downloading image: {}", err);
-                        return;
-                    },
-                };
-
-                // save aufname locally
-                if let Err(err) = image_store.store_image(&image_path, &image).await {
-                    error!("Error downloading image: {}", err);
-                };
+    async fn handle_event(self: &Arc<Self>, event: com_model::AufnahmeEvent) {
+        // like polling, pushed images wait for the resume, the stream keeps them meanwhile
+        self.wait_while_paused().await;
+        let auftrag_status = com_model::AuftragStatus {
+            position: com_model::ServerStatus { runde: event.runde, aufnahme: event.aufnahme },
+            fertig: None,
+        };
+        match event.into_aufnahme() {
+            Ok(aufnahme) => {
+                if let Some(position) = aufnahme.capture_position() {
+                    self.event_positions.lock().await.insert(aufnahme.id.clone(), position);
+                }
+                if !self.image_store.known_names().await.contains(&aufnahme.local_name) {
+                    self.schedule_download(aufnahme).await;
+                }
+            }
+            Err(err) => warn!("{}", err),
+        }
+        let new_status = self.to_app_status(auftrag_status).await;
+        if new_status == ImageAppStatus::Finished {
+            // the last look at the index runs aside, events of recaptures still name their shot meanwhile
+            let downloader = Arc::clone(self);
+            let task = tokio::spawn(async move {
+                downloader.set_status(new_status).await;
+                downloader.notifie_ws().await;
             });
-            task_handles.push(t);
+            self.tasks.lock().await.push(task);
+        } else {
+            self.set_status(new_status).await;
+            self.notifie_ws().await;
         }
+    }
+
+    async fn keep_polling(&self) -> bool {
+        !self.cancellation.is_cancelled()
+            && (!self.app_image_status.lock().await.is_done()
+                || !self.pending_recaptures.lock().await.is_empty())
+    }
+
+    // the index lists the images in the order they were taken, which links the n-th image to
+    // the n-th shot of the Auftrag until a shot is taken again
+    async fn get_new_images(&self) -> Result<Vec<com_model::Aufnahme>, Box<dyn Error + Send>> {
+        let available_images = self.client.get_ready_image_list().await
+            .map_err(|err| -> Box<dyn Error + Send> { Box::new(err) })?;
+        let in_shot_order = !*self.recaptured.lock().await;
+        let event_positions = self.event_positions.lock().await;
+        let old_images = self.image_store.known_names().await;
+        Ok(available_images.into_iter()
+            .enumerate()
+            .filter(|(_, aufnahme)| !old_images.contains(&aufnahme.local_name))
+            .map(|(index, aufnahme)| {
+                let position = event_positions.get(&aufnahme.id).cloned()
+                    .or_else(|| if in_shot_order { self.completion.auftrag.position_of(index) } else { None });
+                aufnahme.captured_at(position.as_ref())
+            })
+            .collect())
+    }
 
-        for task_handle in task_handles {
-            task_handle.await.expect("unable to wait for image_downloading task");
+    async fn download_images(&self) -> Result<(), Box<dyn Error + Send>> {
+        for aufnahme in self.get_new_images().await? {
+            self.schedule_download(aufnahme).await;
         }
         Ok(())
     }
 
+    // the websocket is notified once the image is stored, a recapture which could not be
+    // stored waits for the next image of its shot
+    async fn schedule_download(&self, aufnahme: com_model::Aufnahme) {
+        let recapture = {
+            let mut pending_recaptures = self.pending_recaptures.lock().await;
+            pending_recaptures.iter()
+                .position(|recapture| recapture.matches(&aufnahme))
+                .map(|index| pending_recaptures.remove(index))
+        };
+        let replaces = recapture.as_ref().map(|recapture| recapture.image_name.clone());
+        match self.scheduler.enqueue(DownloadRequest { aufnahme, replaces }).await {
+            Ok(download) => {
+                let notification_handle = Arc::clone(&self.notification_handle);
+                let pending_recaptures = Arc::clone(&self.pending_recaptures);
+                tokio::spawn(async move {
+                    if download.await.unwrap_or(false) {
+                        notifie_ws(notification_handle);
+                    } else if let Some(recapture) = recapture {
+                        pending_recaptures.lock().await.push(recapture);
+                    }
+                });
+            }
+            Err(_) => {
+                if let Some(recapture) = recapture {
+                    self.pending_recaptures.lock().await.push(recapture);
+                }
+            }
+        }
+    }
+
+    pub async fn get_download_stats(&self) -> DownloadStats {
+        self.scheduler.stats().await
+    }
+
     async fn get_new_status(&self) -> Result<Option<ImageAppStatus>, Box<dyn Error + Send>> {
-        let server_status = server_com::get_status(&self.url.clone()).await
+        let auftrag_status = self.client.get_status().await
             .map_err(|err| -> Box<dyn Error + Send> { Box::new(err) })?;
-        let new_server_status = if server_status.eq(&self.target_server_status) {
-            ImageAppStatus::Finished
-        } else {
-            ImageAppStatus::TakingImages(server_status)
-        };
+        let new_server_status = self.to_app_status(auftrag_status).await;
 
         if self.app_image_status.lock().await.eq(&new_server_status) {
             Ok(None)
@@ -172,30 +785,210 @@ impl ImageDownloader {
         }
     }
 
-    async fn notifie_ws(&self) {
-        let notification_handle = Arc::clone(&self.notification_handle);
-        tokio::task::spawn_blocking(move || {
-            let notification_handle_option = notification_handle
-                .lock()
-                .unwrap();
-            if let Some(notification_handle) = notification_handle_option.deref() {
-                notification_handle.do_send(Notification("new image".to_string()));
-            } else {
-                info!("websocket not available update message was therefore not send")
+    async fn to_app_status(&self, auftrag_status: com_model::AuftragStatus) -> ImageAppStatus {
+        let position = auftrag_status.position;
+        let went_idle = {
+            let mut progress_seen = self.progress_seen.lock().await;
+            if !position.is_idle() {
+                *progress_seen = true;
+            }
+            *progress_seen && position.is_idle()
+        };
+        let done = auftrag_status.fertig == Some(true)
+            || went_idle
+            || position == self.completion.target_server_status
+            || self.image_store.image_count().await >= self.completion.expected_images;
+        if done {
+            ImageAppStatus::Finished
+        } else {
+            ImageAppStatus::TakingImages(position)
+        }
+    }
+
+    // Finished is only reported after a last look at the image index
+    async fn set_status(&self, new_status: ImageAppStatus) {
+        if new_status != ImageAppStatus::Finished {
+            *self.app_image_status.lock().await = new_status;
+            return;
+        }
+        if self.app_image_status.lock().await.is_done() {
+            return;
+        }
+        {
+            let mut finishing = self.finishing.lock().await;
+            if *finishing {
+                return;
             }
+            *finishing = true;
+        }
+        self.reconcile().await;
+        *self.app_image_status.lock().await = ImageAppStatus::Finished;
+        *self.finishing.lock().await = false;
+    }
+
+    async fn reconcile(&self) {
+        info!("Auftrag complete, checking for late images");
+        delay_for(tokio::time::Duration::from_secs(RECONCILE_DELAY)).await;
+        if let Err(err) = self.download_images().await {
+            error!("failed to download late images: {}", err);
+        }
+        self.scheduler.wait_idle().await;
+        let image_count = self.image_store.image_count().await;
+        if image_count < self.completion.expected_images {
+            warn!("only {} of {} expected images were downloaded", image_count, self.completion.expected_images);
+        }
+    }
+
+    async fn notifie_ws(&self) {
+        notifie_ws(Arc::clone(&self.notification_handle));
+    }
+
+    /// Asks the server to take the shot of the image again, the image is replaced once an image
+    /// of the same shot arrives. Err(None) if the image or its capture position is unknown.
+    pub async fn request_recapture(self: Arc<Self>, image_name: &str) -> Result<(), Option<ServerError>> {
+        if !self.server_info.supports(com_model::ServerInfo::WIEDERHOLUNG) {
+            return Err(Some(ServerError::ProtocolMismatch(com_model::ServerInfo::WIEDERHOLUNG.to_string())));
+        }
+        let metadata = self.image_store.get_metadata(image_name).await.ok_or(None)?;
+        let (runde, aufnahme) = match (metadata.round, metadata.shot) {
+            (Some(round), Some(shot)) => (round, shot),
+            _ => return Err(None),
+        };
+        let antwort = self.client.post_wiederholung(com_model::Wiederholung { runde, aufnahme }).await
+            .map_err(Some)?;
+        let id = antwort.path.as_deref()
+            .and_then(|path| com_model::Aufnahme::from_server_path(path).ok())
+            .map(|announced| announced.id);
+        *self.recaptured.lock().await = true;
+        self.pending_recaptures.lock().await.push(PendingRecapture {
+            image_name: image_name.to_string(),
+            position: com_model::ServerStatus { runde, aufnahme },
+            id,
         });
+        self.start().await;
+        Ok(())
+    }
+
+    pub async fn get_versions(&self, image_name: &str) -> Option<Vec<ImageVersion>> {
+        self.image_store.get_versions(image_name).await
+    }
+
+    pub fn expected_images(&self) -> usize {
+        self.completion.expected_images
+    }
+
+    pub fn server_url(&self) -> &reqwest::Url {
+        self.client.base_url()
+    }
+
+    /// Stops polling and downloading and asks the server to abort the Auftrag, the report
+    /// tells whether both sides are idle afterwards.
+    pub(crate) async fn cancel(&self) -> ResetReport {
+        self.cancellation.cancel();
+        let (server_cancelled, server_error) = self.cancel_on_server().await;
+        let tasks = self.tasks.lock().await.drain(..).collect::<Vec<_>>();
+        for task in tasks {
+            if let Err(err) = task.await {
+                error!("scan task failed: {}", err);
+            }
+        }
+        self.scheduler.wait_idle().await;
+        let server_idle = server_error.is_none() && self.wait_for_idle_server().await;
+        ResetReport {
+            client_idle: true,
+            aborted_downloads: self.scheduler.stats().await.cancelled,
+            server_cancelled,
+            server_idle,
+            server_error,
+        }
     }
 
-    pub(crate) async fn reset(&self) {
-        *self.reset.lock().await = true;
+    async fn cancel_on_server(&self) -> (bool, Option<String>) {
+        if !self.server_info.supports(com_model::ServerInfo::ABBRUCH) {
+            warn!("server does not support cancelling an Auftrag");
+            return (false, None);
+        }
+        match self.client.post_abbruch().await {
+            Ok(()) => (true, None),
+            Err(err) => {
+                error!("unable to cancel the Auftrag on the server: {}", err);
+                (false, Some(err.to_string()))
+            }
+        }
+    }
+
+    async fn wait_for_idle_server(&self) -> bool {
+        let started_at = Instant::now();
+        while started_at.elapsed() < Duration::from_secs(IDLE_CONFIRM_TIMEOUT) {
+            match self.client.get_status().await {
+                Ok(auftrag_status) if auftrag_status.position.is_idle() || auftrag_status.fertig == Some(true) => {
+                    return true;
+                }
+                Ok(_) => {}
+                Err(err) => warn!("unable to get status after cancelling: {}", err),
+            }
+            delay_for(Duration::from_secs(1)).await;
+        }
+        false
     }
 
     pub async fn get_image_list(&self) -> Vec<String> {
         self.image_store.get_image_list().await
     }
 
+    pub async fn get_excluded_list(&self) -> Vec<String> {
+        self.image_store.get_excluded_list().await
+    }
+
+    pub async fn set_excluded(&self, image_name: &str, exclude: bool) -> Result<(), Option<tokio::io::Error>> {
+        self.image_store.set_excluded(image_name, exclude).await
+    }
+
     pub async fn get_image(&self, image_name: &String) -> Result<Vec<u8>, Option<tokio::io::Error>> {
         self.image_store.get_image(image_name).await
     }
+
+    pub async fn get_metadata_list(&self) -> Vec<ImageMetadata> {
+        self.image_store.get_metadata_list().await
+    }
+
+    pub async fn get_metadata(&self, image_name: &str) -> Option<ImageMetadata> {
+        self.image_store.get_metadata(image_name).await
+    }
+
+    pub async fn get_quality_report(&self) -> QualityReport {
+        self.image_store.get_quality_report().await
+    }
+
+    pub async fn get_thumbnail(&self, image_name: &String, size: ThumbnailSize) -> Result<Vec<u8>, Option<tokio::io::Error>> {
+        self.image_store.get_thumbnail(image_name, size).await
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn exclusions_belong_to_their_session() {
+        let _folder = paths::lock_test_folder().await;
+        let image = com_model::Aufnahme::from_server_path("img_1.jpg").unwrap();
+        // the same server and rounds give the images of every scan the same names
+        let (first_scan, second_scan) = (new_session_id(), new_session_id());
+
+        let store = ImageStore::new(&first_scan).await.unwrap();
+        store.store_image(&image, b"first object").await.unwrap();
+        store.set_excluded("img_1.jpg", true).await.unwrap();
+
+        // opened again, the session keeps its exclusions
+        let store = ImageStore::new(&first_scan).await.unwrap();
+        store.store_image(&image, b"first object").await.unwrap();
+        assert_eq!(store.get_excluded_list().await, vec!["img_1.jpg".to_string()]);
+
+        let store = ImageStore::new(&second_scan).await.unwrap();
+        store.store_image(&image, b"second object").await.unwrap();
+        assert!(store.get_excluded_list().await.is_empty());
+        assert!(paths::image_folder().join("img_1.jpg").exists());
+    }
+}
@@ -0,0 +1,68 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use exif::{Exif, In, Tag, Value};
+use log::debug;
+use crate::server_com::com_model::ServerStatus;
+
+#[derive(Serialize, JsonSchema, Clone, Default, Debug)]
+pub struct ImageMetadata {
+    pub name: String,
+    pub round: Option<i32>,
+    pub shot: Option<i32>,
+    pub camera_model: Option<String>,
+    pub focal_length: Option<f64>, // in mm
+    pub exposure_time: Option<f64>, // in seconds
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    pub timestamp: Option<String>,
+    pub orientation: Option<u32>,
+}
+
+impl ImageMetadata {
+    /// Parses the exif data of the image and links it to the server status it was captured at.
+    pub fn read(name: &str, image: &[u8], captured_at: Option<&ServerStatus>) -> ImageMetadata {
+        let mut metadata = ImageMetadata {
+            name: name.to_string(),
+            round: captured_at.map(|status| status.runde),
+            shot: captured_at.map(|status| status.aufnahme),
+            ..ImageMetadata::default()
+        };
+
+        let exif = match exif::Reader::new().read_from_container(&mut std::io::Cursor::new(image)) {
+            Ok(exif) => exif,
+            Err(err) => {
+                debug!("no exif data in {}: {}", name, err);
+                return metadata;
+            }
+        };
+
+        metadata.camera_model = ascii_field(&exif, Tag::Model);
+        metadata.focal_length = rational_field(&exif, Tag::FocalLength);
+        metadata.exposure_time = rational_field(&exif, Tag::ExposureTime);
+        metadata.f_number = rational_field(&exif, Tag::FNumber);
+        metadata.iso = uint_field(&exif, Tag::PhotographicSensitivity);
+        metadata.timestamp = ascii_field(&exif, Tag::DateTimeOriginal)
+            .or_else(|| ascii_field(&exif, Tag::DateTime));
+        metadata.orientation = uint_field(&exif, Tag::Orientation);
+        metadata
+    }
+}
+
+fn ascii_field(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_string()),
+        _ => None,
+    }
+}
+
+fn rational_field(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values.first().map(|value| value.to_f64()),
+        _ => None,
+    }
+}
+
+fn uint_field(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
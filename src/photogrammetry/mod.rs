@@ -1,3 +1,9 @@
+pub mod cancellation;
+pub mod downloads;
 pub mod image_handling;
+pub mod metadata;
+#[allow(clippy::module_inception)]
 pub mod photogrammetry;
-mod paths;
\ No newline at end of file
+pub mod quality;
+pub mod thumbnails;
+pub mod paths;
@@ -1,10 +1,41 @@
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::OnceLock;
+
+// the headless client scans into a folder of the user's choice instead of /ph
+static PARENT_FOLDER: OnceLock<PathBuf> = OnceLock::new();
+
+/// Moves the folders of the scan below `folder`, before any of them is used. Fails if it was moved already.
+pub fn set_parent_folder(folder: PathBuf) -> Result<(), PathBuf> { PARENT_FOLDER.set(folder) }
+
+/// Moves the folders below the temp dir for a test. The tests share them, so they take turns.
+#[cfg(test)]
+pub async fn lock_test_folder() -> tokio::sync::MutexGuard<'static, ()> {
+    static LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+    PARENT_FOLDER.get_or_init(|| std::env::temp_dir().join(format!("scaned_client_test_{}", std::process::id())));
+    LOCK.get_or_init(|| tokio::sync::Mutex::new(())).lock().await
+}
 
 pub fn texture_folder() -> PathBuf {parent_folder().join("odm_texturing")}
 
-pub fn parent_folder() -> PathBuf { PathBuf::from_str("/ph").unwrap() }
+pub fn parent_folder() -> PathBuf {
+    PARENT_FOLDER.get().cloned().unwrap_or_else(|| PathBuf::from_str("/ph").unwrap())
+}
 
 pub fn archive_file() -> PathBuf { PathBuf::from_str("/model.zip").unwrap() }
 
 pub fn image_folder() -> PathBuf { parent_folder().join("images") }
+
+// images the user excluded from the reconstruction
+pub fn excluded_folder() -> PathBuf { parent_folder().join("excluded") }
+
+pub fn exclusion_file() -> PathBuf { parent_folder().join("excluded.json") }
+
+// replaced versions of recaptured images
+pub fn history_folder() -> PathBuf { parent_folder().join("history") }
+
+// interrupted downloads, resumed on the next attempt
+pub fn partial_folder() -> PathBuf { parent_folder().join("partial") }
+
+// kept outside of the image folder, OpenDroneMap uses every file in there
+pub fn thumbnail_folder() -> PathBuf { parent_folder().join("thumbnails") }
@@ -2,11 +2,12 @@ use std::sync::{Arc};
 use crate::web_interface::model::ws::{Notification};
 use tokio::sync::{oneshot, Mutex};
 use tokio::process::{Command, ChildStdout, ChildStderr};
-use std::process::Stdio;
+use std::process::{ExitStatus, Stdio};
 use tokio::io::{BufReader, AsyncBufReadExt, Lines};
 use std::fs::File;
 use serde_json::json;
 use serde::{Serialize};
+use schemars::JsonSchema;
 use log::{info, warn, error, debug};
 use crate::photogrammetry::paths;
 use crate::web_interface::model::NotificationHandle;
@@ -14,12 +15,21 @@ use tokio::io;
 use crate::photogrammetry::photogrammetry::Message::{NewConsoleOutput, Finished};
 use tokio::task::JoinHandle;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 pub type ConsoleOutput = Arc<Mutex<Vec<serde_json::Value>>>;
 
-#[derive(Serialize, Clone)]
+// stages of OpenDroneMap in the order they run
+pub const ODM_STAGES: [&str; 13] = [
+    "dataset", "split", "merge", "opensfm", "openmvs", "odm_filterpoints", "odm_meshing",
+    "mvs_texturing", "odm_georeferencing", "odm_dem", "odm_orthophoto", "odm_report", "odm_postprocess",
+];
+
+/// Line of the console output, sent over the websocket and stored for the log.
+#[derive(Serialize, JsonSchema, Clone)]
 #[serde(tag = "type")]
-enum Message {
+pub enum Message {
     NewConsoleOutput(MessageBody),
     Error(MessageBody),
     Finished,
@@ -31,8 +41,8 @@ impl Message {
     }
 }
 
-#[derive(Serialize, Clone)]
-struct MessageBody {
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct MessageBody {
     body: String
 }
 
@@ -48,7 +58,120 @@ impl From<&str> for MessageBody {
     }
 }
 
-struct ConsoleReader {
+#[derive(Serialize, JsonSchema)]
+pub struct ReconstructionProgress {
+    pub finished: bool,
+    // stage OpenDroneMap reported last
+    pub stage: Option<String>,
+    // stages which were started, of stage_count
+    pub started_stages: usize,
+    pub stage_count: usize,
+    pub lines: usize,
+    pub errors: usize,
+}
+
+/// Reads the progress of the reconstruction from the console output of OpenDroneMap.
+pub fn progress(console_output: &[serde_json::Value]) -> ReconstructionProgress {
+    let stage = console_output.iter()
+        .filter_map(|line| line["body"].as_str())
+        .filter_map(running_stage)
+        .next_back();
+    let started_stages = stage
+        .and_then(|stage| ODM_STAGES.iter().position(|known| *known == stage))
+        .map_or(0, |index| index + 1);
+    ReconstructionProgress {
+        finished: console_output.iter().any(|line| line["type"] == "Finished"),
+        stage: stage.map(str::to_string),
+        started_stages,
+        stage_count: ODM_STAGES.len(),
+        lines: console_output.len(),
+        errors: console_output.iter().filter(|line| line["type"] == "Error").count(),
+    }
+}
+
+// OpenDroneMap logs "Running <stage> stage" when a stage starts
+pub fn running_stage(line: &str) -> Option<&str> {
+    let start = line.find("Running ")? + "Running ".len();
+    let stage = line[start..].strip_suffix(" stage")?;
+    Some(stage.trim())
+}
+
+/// Trade-off between the time a reconstruction takes and the detail of the model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    Fast,
+    Default,
+    High,
+}
+
+impl Preset {
+    fn odm_args(self) -> &'static [&'static str] {
+        match self {
+            Preset::Fast => &[
+                "--feature-quality", "low", "--pc-quality", "low", "--mesh-size", "100000",
+                "--skip-orthophoto", "--skip-report",
+            ],
+            // the defaults of OpenDroneMap
+            Preset::Default => &[],
+            Preset::High => &["--feature-quality", "high", "--pc-quality", "high", "--mesh-size", "300000"],
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(preset: &str) -> Result<Self, Self::Err> {
+        match preset {
+            "fast" => Ok(Preset::Fast),
+            "default" => Ok(Preset::Default),
+            "high" => Ok(Preset::High),
+            _ => Err(format!("unknown preset {}, expected fast, default or high", preset)),
+        }
+    }
+}
+
+/// How OpenDroneMap is run, the default is the project of the web interface.
+pub struct OdmOptions {
+    // run.py of OpenDroneMap
+    pub script: PathBuf,
+    // folder containing the project folder, the one configured in OpenDroneMap if None
+    pub project_path: Option<PathBuf>,
+    pub project_name: String,
+    pub preset: Preset,
+    // additionally writes the model as glTF binary
+    pub gltf: bool,
+}
+
+impl Default for OdmOptions {
+    fn default() -> Self {
+        OdmOptions {
+            script: PathBuf::from("run.py"),
+            project_path: None,
+            project_name: "ph".to_string(),
+            preset: Preset::Default,
+            gltf: false,
+        }
+    }
+}
+
+impl OdmOptions {
+    fn args(&self) -> Vec<std::ffi::OsString> {
+        let mut args = vec!["-u".into(), self.script.clone().into_os_string()];
+        if let Some(project_path) = &self.project_path {
+            args.push("--project-path".into());
+            args.push(project_path.clone().into_os_string());
+        }
+        args.extend(self.preset.odm_args().iter().map(|arg| arg.into()));
+        if self.gltf {
+            args.push("--gltf".into());
+        }
+        args.push(self.project_name.clone().into());
+        args
+    }
+}
+
+pub struct ConsoleReader {
     stdout_reader: Lines<BufReader<ChildStdout>>,
     stderr_reader: Lines<BufReader<ChildStderr>>,
 }
@@ -60,7 +183,7 @@ impl ConsoleReader {
         ConsoleReader { stdout_reader, stderr_reader }
     }
 
-    async fn next_line(&mut self) -> io::Result<Option<String>> {
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
         tokio::select! {
             stdout_res = self.stdout_reader.next_line() => {
                 stdout_res
@@ -72,16 +195,17 @@ impl ConsoleReader {
     }
 }
 
-async fn start_process(
+/// Starts OpenDroneMap, the handle resolves to its exit status or None if it was shut down.
+pub async fn start_process(
+    options: &OdmOptions,
     shutdown_hook: oneshot::Receiver<()>
-) -> (ConsoleReader, JoinHandle<()>) {
+) -> io::Result<(ConsoleReader, JoinHandle<Option<ExitStatus>>)> {
     let mut cmd = Command::new("python3");
-    cmd.args(&["-u", "run.py", "ph"]);
+    cmd.args(options.args());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
 
-    let mut child = cmd.spawn()
-        .expect("failed to spawn command");
+    let mut child = cmd.spawn()?;
 
     let stdout = child.stdout.take()
         .expect("child did not have a handle to stdout");
@@ -91,16 +215,18 @@ async fn start_process(
 
     let join_handle = tokio::spawn(async move {
         tokio::select! {
-            _ = child => {
+            status = child => {
                 info!("photogrammetry process finished");
+                status.ok()
             }
             _ = shutdown_hook => {
                 warn!("user canceled photogrammetry process");
+                None
             }
         }
     });
 
-    (ConsoleReader::new(stdout, stderr), join_handle)
+    Ok((ConsoleReader::new(stdout, stderr), join_handle))
 }
 
 pub async fn start_photogrammetry(ws: NotificationHandle,
@@ -108,7 +234,17 @@ pub async fn start_photogrammetry(ws: NotificationHandle,
                                   shutdown_process_rx: oneshot::Receiver<()>) -> JoinHandle<()> {
     tokio::spawn(async move {
     let (mut console_reader, process_join_handle) =
-        start_process(shutdown_process_rx).await;
+        match start_process(&OdmOptions::default(), shutdown_process_rx).await {
+            Ok(process) => process,
+            Err(err) => {
+                let error_msg = Message::Error(format!("unable to start OpenDroneMap: {}", err).into());
+                error!("{}", error_msg.clone().into_json());
+                send_over_ws(ws.clone(), &error_msg.clone().into_json()).await;
+                console_output.lock().await.push(json!(error_msg));
+                console_output.lock().await.push(json!(Finished));
+                return;
+            }
+        };
     loop {
         let line = match console_reader.next_line().await {
             Ok(line) => { line }
@@ -140,7 +276,11 @@ pub async fn start_photogrammetry(ws: NotificationHandle,
             error!("{}", error_msg);
             send_over_ws(ws.clone(), &Message::Error(error_msg.into()).into_json()).await;
         }
-        tokio::task::spawn_blocking(zip_3d_model)
+        tokio::task::spawn_blocking(|| {
+            if let Err(err) = zip_model(&paths::texture_folder(), &paths::archive_file()) {
+                error!("unable to zip the 3d model: {}", err);
+            }
+        });
     });
     })
 }
@@ -157,11 +297,12 @@ async fn send_over_ws(ws: NotificationHandle, msg: &str) {
     });
 }
 
-fn zip_3d_model() {
+/// Packs the textured model of OpenDroneMap into a zip archive.
+pub fn zip_model(texture_folder: &Path, archive: &Path) -> zip::result::ZipResult<()> {
     use zip::ZipWriter;
     use zip_extensions::write::ZipWriterExtensions;
 
-    let file = File::create(paths::archive_file()).unwrap();
+    let file = File::create(archive)?;
     let mut zip = ZipWriter::new(file);
-    zip.create_from_directory(&paths::texture_folder()).unwrap()
-}
\ No newline at end of file
+    zip.create_from_directory(&texture_folder.to_path_buf())
+}
@@ -0,0 +1,159 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+
+// images are scaled down before the analysis, the metrics are relative to this size
+const ANALYSIS_EDGE: u32 = 800;
+const HISTOGRAM_BINS: usize = 16;
+
+const BLUR_THRESHOLD: f64 = 100.0; // variance of laplacian below this counts as blurry
+const CLIPPED_FRACTION: f64 = 0.25; // share of pixels in the outermost histogram bin
+const DUPLICATE_DISTANCE: u32 = 3; // hamming distance of the average hashes
+const LOW_OVERLAP_DISTANCE: u32 = 28;
+const MAX_FLAGGED_FRACTION: f64 = 0.25; // above this share of flagged images the set is not ready
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub enum QualityFlag {
+    Blurry,
+    Underexposed,
+    Overexposed,
+    NearDuplicate,
+    LowOverlap,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub enum Readiness {
+    Ready,
+    Warnings,
+    NotReady,
+}
+
+/// Metrics computed once for every image when it is stored.
+#[derive(Clone, Debug)]
+pub struct ImageQuality {
+    pub blur: f64,
+    pub mean_brightness: f64,
+    pub histogram: Vec<f64>,
+    average_hash: u64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct ImageQualityReport {
+    pub name: String,
+    pub blur: f64,
+    pub mean_brightness: f64,
+    pub histogram: Vec<f64>,
+    pub flags: Vec<QualityFlag>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct QualityReport {
+    pub verdict: Readiness,
+    pub images: Vec<ImageQualityReport>,
+}
+
+impl ImageQuality {
+    pub async fn analyse(image: Vec<u8>) -> Result<ImageQuality, image::ImageError> {
+        tokio::task::spawn_blocking(move || {
+            Ok(ImageQuality::of_image(&image::load_from_memory(&image)?))
+        }).await.unwrap_or_else(|err| Err(std::io::Error::other(err).into()))
+    }
+
+    /// Computes the metrics of an already decoded image, blocks.
+    pub fn of_image(image: &DynamicImage) -> ImageQuality {
+        let gray = image.resize(ANALYSIS_EDGE, ANALYSIS_EDGE, FilterType::Triangle).to_luma8();
+        let (histogram, mean_brightness) = histogram(&gray);
+        ImageQuality {
+            blur: laplacian_variance(&gray),
+            mean_brightness,
+            histogram,
+            average_hash: average_hash(&gray),
+        }
+    }
+
+    pub fn flags(&self) -> Vec<QualityFlag> {
+        let mut flags = Vec::new();
+        if self.blur < BLUR_THRESHOLD {
+            flags.push(QualityFlag::Blurry);
+        }
+        if self.histogram[0] > CLIPPED_FRACTION {
+            flags.push(QualityFlag::Underexposed);
+        }
+        if self.histogram[HISTOGRAM_BINS - 1] > CLIPPED_FRACTION {
+            flags.push(QualityFlag::Overexposed);
+        }
+        flags
+    }
+}
+
+/// Builds the report for images in capture order, neighbouring shots are compared for
+/// duplicates and missing overlap.
+pub fn report(images: Vec<(String, ImageQuality)>) -> QualityReport {
+    let mut reports = Vec::new();
+    for (i, (name, quality)) in images.iter().enumerate() {
+        let mut flags = quality.flags();
+        if let Some((_, previous)) = i.checked_sub(1).and_then(|i| images.get(i)) {
+            let distance = (quality.average_hash ^ previous.average_hash).count_ones();
+            if distance <= DUPLICATE_DISTANCE {
+                flags.push(QualityFlag::NearDuplicate);
+            } else if distance >= LOW_OVERLAP_DISTANCE {
+                flags.push(QualityFlag::LowOverlap);
+            }
+        }
+        reports.push(ImageQualityReport {
+            name: name.clone(),
+            blur: quality.blur,
+            mean_brightness: quality.mean_brightness,
+            histogram: quality.histogram.clone(),
+            flags,
+        });
+    }
+
+    let flagged = reports.iter().filter(|report| !report.flags.is_empty()).count();
+    let verdict = if reports.is_empty()
+        || flagged as f64 > reports.len() as f64 * MAX_FLAGGED_FRACTION {
+        Readiness::NotReady
+    } else if flagged > 0 {
+        Readiness::Warnings
+    } else {
+        Readiness::Ready
+    };
+    QualityReport { verdict, images: reports }
+}
+
+fn laplacian_variance(gray: &GrayImage) -> f64 {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let px = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+    let mut values = Vec::with_capacity(((width - 2) * (height - 2)) as usize);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            values.push(px(x - 1, y) + px(x + 1, y) + px(x, y - 1) + px(x, y + 1) - 4.0 * px(x, y));
+        }
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / values.len() as f64
+}
+
+// normalized luminance histogram and mean brightness in the range 0..1
+fn histogram(gray: &GrayImage) -> (Vec<f64>, f64) {
+    let mut bins = [0u64; HISTOGRAM_BINS];
+    let mut sum = 0u64;
+    for pixel in gray.pixels() {
+        bins[pixel[0] as usize * HISTOGRAM_BINS / 256] += 1;
+        sum += pixel[0] as u64;
+    }
+    let count = (gray.width() * gray.height()).max(1) as f64;
+    (bins.iter().map(|bin| *bin as f64 / count).collect(), sum as f64 / count / 255.0)
+}
+
+fn average_hash(gray: &GrayImage) -> u64 {
+    let small = image::imageops::resize(gray, 8, 8, FilterType::Triangle);
+    let mean = small.pixels().map(|pixel| pixel[0] as u32).sum::<u32>() / 64;
+    small.pixels().enumerate()
+        .filter(|(_, pixel)| pixel[0] as u32 > mean)
+        .fold(0u64, |hash, (i, _)| hash | 1 << i)
+}
@@ -0,0 +1,62 @@
+use serde::Deserialize;
+use schemars::JsonSchema;
+use std::path::PathBuf;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat};
+use crate::photogrammetry::paths;
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [ThumbnailSize; 3] = [ThumbnailSize::Small, ThumbnailSize::Medium, ThumbnailSize::Large];
+
+    // bounding box edge length in pixels
+    pub fn max_edge(self) -> u32 {
+        match self {
+            ThumbnailSize::Small => 200,
+            ThumbnailSize::Medium => 400,
+            ThumbnailSize::Large => 800,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ThumbnailSize::Small => "small",
+            ThumbnailSize::Medium => "medium",
+            ThumbnailSize::Large => "large",
+        }
+    }
+
+    fn folder(self) -> PathBuf {
+        paths::thumbnail_folder().join(self.name())
+    }
+}
+
+pub async fn init_dir() -> tokio::io::Result<()> {
+    for size in ThumbnailSize::ALL.iter() {
+        tokio::fs::create_dir_all(size.folder()).await?;
+    }
+    Ok(())
+}
+
+/// Scales the decoded image down to every thumbnail size and caches the results as jpeg on disk.
+/// Blocks, call it from a blocking task.
+pub fn write_thumbnails(name: &str, image: &DynamicImage) -> Result<(), image::ImageError> {
+    for size in ThumbnailSize::ALL.iter() {
+        let thumbnail = image.resize(size.max_edge(), size.max_edge(), FilterType::Triangle);
+        let mut buf = Vec::new();
+        thumbnail.write_to(&mut buf, ImageOutputFormat::Jpeg(80))?;
+        std::fs::write(size.folder().join(name), buf)?;
+    }
+    Ok(())
+}
+
+pub async fn read_thumbnail(name: &str, size: ThumbnailSize) -> tokio::io::Result<Vec<u8>> {
+    tokio::fs::read(size.folder().join(name)).await
+}
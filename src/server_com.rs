@@ -1,74 +1,634 @@
-use reqwest::{Response, Url};
-use std::str::FromStr;
-use crate::server_com::com_model::{ServerStatus, Auftrag};
-use std::collections::HashSet;
-use std::error::Error;
-use log::{info};
+#![allow(clippy::redundant_static_lifetimes)]
+
+use reqwest::{Client, Response, StatusCode, Url};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use actix_web::web::Bytes;
+use crate::server_com::com_model::{AuftragStatus, Auftrag, Wiederholung, WiederholungAntwort, ServerInfo, AufnahmeEvent, Aufnahme};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+use log::{info, warn};
 
 const AUFTRAG_ENPOINT: &'static str = "auftrag";
 const AUFNAHMEN_ENDPOINT: &'static str = "aufnahme";
+const WIEDERHOLUNG_ENDPOINT: &'static str = "wiederholung";
+const VERSION_ENDPOINT: &'static str = "version";
+const EVENTS_ENDPOINT: &'static str = "events";
+const ABBRUCH_ENDPOINT: &'static str = "abbruch";
+const PAUSE_ENDPOINT: &'static str = "pause";
+const FORTSETZEN_ENDPOINT: &'static str = "fortsetzen";
+
+// major version of the server protocol this client understands
+const SUPPORTED_PROTOCOL_VERSION: u32 = 1;
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 5; // in seconds
+const DEFAULT_REQUEST_TIMEOUT: u64 = 30; // in seconds
 
 pub mod com_model {
     use serde::{Serialize, Deserialize};
+    use schemars::JsonSchema;
+    use super::{ServerError, ServerResult, AUFNAHMEN_ENDPOINT};
 
-    #[derive(Deserialize, Serialize, Clone, PartialEq)]
+    #[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
     pub struct ServerStatus {
-        runde: i32,
-        aufnahme: i32,
+        pub runde: i32,
+        pub aufnahme: i32,
     }
 
-    #[derive(Deserialize, Serialize, Debug)]
+    impl ServerStatus {
+        // reported by servers which are not working on an Auftrag
+        pub fn is_idle(&self) -> bool {
+            self.runde == 0 && self.aufnahme == 0
+        }
+    }
+
+    /// Response of the auftrag endpoint, newer servers flag a completed Auftrag.
+    #[derive(Deserialize, Serialize, Clone)]
+    pub struct AuftragStatus {
+        #[serde(flatten)]
+        pub position: ServerStatus,
+        #[serde(default)]
+        pub fertig: Option<bool>,
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Debug)]
     pub struct Auftrag {
         pub auftrag: Vec<i32>
     }
 
+    /// Asks the server to take a single shot of the current Auftrag again.
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct Wiederholung {
+        pub runde: i32,
+        pub aufnahme: i32,
+    }
+
+    /// Answer to a Wiederholung, newer servers name the path the new image will be listed under.
+    #[derive(Deserialize, Serialize, Debug, Default)]
+    pub struct WiederholungAntwort {
+        #[serde(default)]
+        pub path: Option<String>,
+    }
+
+    /// Version and capabilities reported by the server during the handshake.
+    #[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+    pub struct ServerInfo {
+        pub version: String,
+        pub camera_model: Option<String>,
+        pub features: Vec<String>,
+        #[serde(default)]
+        pub legacy: bool,
+    }
+
+    impl ServerInfo {
+        pub const AUFTRAG: &'static str = "auftrag";
+        pub const AUFNAHME: &'static str = "aufnahme";
+        pub const WIEDERHOLUNG: &'static str = "wiederholung";
+        pub const EVENTS: &'static str = "events";
+        pub const ABBRUCH: &'static str = "abbruch";
+        // pause and fortsetzen
+        pub const PAUSE: &'static str = "pause";
+
+        /// Servers without a version endpoint only know about auftrag and aufnahme.
+        pub fn legacy() -> ServerInfo {
+            ServerInfo {
+                version: "legacy".to_string(),
+                camera_model: None,
+                features: vec![ServerInfo::AUFTRAG.to_string(), ServerInfo::AUFNAHME.to_string()],
+                legacy: true,
+            }
+        }
+
+        pub fn supports(&self, feature: &str) -> bool {
+            self.features.iter().any(|supported| supported == feature)
+        }
+
+        pub fn major_version(&self) -> Option<u32> {
+            self.version.split('.').next()?.trim_start_matches('v').parse().ok()
+        }
+    }
+
+    /// Pushed by the server for every image which is ready for download.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct AufnahmeEvent {
+        pub path: String,
+        pub runde: i32,
+        pub aufnahme: i32,
+    }
+
+    /// Identity of an image on the server and the name it is stored under locally.
+    #[derive(Serialize, Clone, Debug, PartialEq)]
+    pub struct Aufnahme {
+        // path relative to the aufnahme endpoint, e.g. runde1/img_3.jpg
+        pub id: String,
+        // path as listed by the server
+        pub server_path: String,
+        // file name in the image folder, never leaves the folder
+        pub local_name: String,
+        pub round: Option<i32>,
+        pub shot: Option<i32>,
+    }
+
+    impl Aufnahme {
+        /// Accepts absolute /aufnahme/... paths, bare file names and nested paths.
+        /// Paths which could escape the aufnahme endpoint or the image folder are rejected.
+        pub fn from_server_path(server_path: &str) -> ServerResult<Aufnahme> {
+            let endpoint_prefix = format!("{}/", AUFNAHMEN_ENDPOINT);
+            let relative = server_path.trim_start_matches('/');
+            let relative = relative.strip_prefix(&endpoint_prefix).unwrap_or(relative);
+            let segments = relative.split('/').collect::<Vec<_>>();
+            let unsafe_segment = |segment: &&str| segment.is_empty()
+                || *segment == "."
+                || *segment == ".."
+                || segment.contains(|c: char| c == '\\' || c.is_control());
+            if segments.iter().any(unsafe_segment) {
+                return Err(ServerError::InvalidImagePath(server_path.to_string()));
+            }
+            Ok(Aufnahme {
+                id: relative.to_string(),
+                server_path: server_path.to_string(),
+                local_name: local_name(relative),
+                round: None,
+                shot: None,
+            })
+        }
+
+        pub fn captured_at(mut self, server_status: Option<&ServerStatus>) -> Aufnahme {
+            self.round = server_status.map(|status| status.runde);
+            self.shot = server_status.map(|status| status.aufnahme);
+            self
+        }
+
+        pub fn capture_position(&self) -> Option<ServerStatus> {
+            Some(ServerStatus { runde: self.round?, aufnahme: self.shot? })
+        }
+    }
+
+    // ids which are a plain file name are kept, others are made portable and get a hash of the
+    // id appended, so ids which only differ in the replaced characters get different names
+    fn local_name(id: &str) -> String {
+        let plain = !id.starts_with('.')
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_');
+        if plain {
+            return id.to_string();
+        }
+        let sanitized = id.split('/').map(local_segment).collect::<Vec<_>>().join("_");
+        let digest = ring::digest::digest(&ring::digest::SHA256, id.as_bytes());
+        let hash = digest.as_ref()[..4].iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        match sanitized.rfind('.') {
+            Some(dot) if dot > 0 => format!("{}-{}{}", &sanitized[..dot], hash, &sanitized[dot..]),
+            _ => format!("{}-{}", sanitized, hash),
+        }
+    }
+
+    // keeps file names portable and prevents hidden files
+    fn local_segment(segment: &str) -> String {
+        segment.char_indices()
+            .map(|(i, c)| match c {
+                '.' if i == 0 => '_',
+                c if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' => c,
+                _ => '_',
+            })
+            .collect()
+    }
+
+    impl AufnahmeEvent {
+        pub fn into_aufnahme(self) -> ServerResult<Aufnahme> {
+            let server_status = ServerStatus { runde: self.runde, aufnahme: self.aufnahme };
+            Ok(Aufnahme::from_server_path(&self.path)?.captured_at(Some(&server_status)))
+        }
+    }
+
     impl Auftrag {
         pub fn from_vec(rounds: Vec<i32>) -> Auftrag {
             Auftrag { auftrag: rounds }
         }
-        pub fn into_target_status(self) -> ServerStatus {
-            ServerStatus {
+
+        /// Position the server reports after the last shot, None for an empty Auftrag.
+        pub fn target_status(&self) -> Option<ServerStatus> {
+            Some(ServerStatus {
                 runde: self.auftrag.len() as i32,
-                aufnahme: *self.auftrag.last().unwrap()
+                aufnahme: *self.auftrag.last()?,
+            })
+        }
+
+        pub fn expected_images(&self) -> usize {
+            self.auftrag.iter().map(|shots| (*shots).max(0) as usize).sum()
+        }
+
+        /// Round and shot of the image at `index` if the images are listed in the order they were
+        /// taken, None past the last shot.
+        pub fn position_of(&self, index: usize) -> Option<ServerStatus> {
+            let mut remaining = index;
+            for (round, shots) in self.auftrag.iter().enumerate() {
+                let shots = (*shots).max(0) as usize;
+                if remaining < shots {
+                    return Some(ServerStatus { runde: round as i32 + 1, aufnahme: remaining as i32 + 1 });
+                }
+                remaining -= shots;
+            }
+            None
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ServerError {
+    #[error("invalid server url {0}: {1}")]
+    InvalidUrl(String, String),
+    #[error("ScanEd server is unreachable: {0}")]
+    Unreachable(reqwest::Error),
+    #[error("request to the ScanEd server timed out")]
+    Timeout,
+    #[error("ScanEd server responded with status {0}")]
+    Status(StatusCode),
+    #[error("unable to decode the response of the ScanEd server: {0}")]
+    Decode(reqwest::Error),
+    #[error("ScanEd server does not support the endpoint {0}")]
+    ProtocolMismatch(String),
+    #[error("ScanEd server version {0} is not compatible with this client")]
+    Incompatible(String),
+    #[error("invalid event from the ScanEd server: {0}")]
+    InvalidEvent(serde_json::Error),
+    #[error("ScanEd server listed an invalid image path {0}")]
+    InvalidImagePath(String),
+    #[error("ScanEd server answered with the range \"{0}\" instead of starting at byte {1}")]
+    RangeMismatch(String, u64),
+}
+
+impl From<reqwest::Error> for ServerError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            ServerError::Timeout
+        } else if err.is_decode() {
+            ServerError::Decode(err)
+        } else {
+            ServerError::Unreachable(err)
+        }
+    }
+}
+
+pub type ServerResult<T> = Result<T, ServerError>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub request: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT),
+            request: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT),
+        }
+    }
+}
+
+/// Client for the http api of the ScanEd server running on the Raspberry Pi.
+#[derive(Clone)]
+pub struct ScanEdClient {
+    client: Client,
+    // without request timeout, for responses which stay open
+    stream_client: Client,
+    base_url: Url,
+}
+
+impl ScanEdClient {
+    pub fn new(url: &str, timeouts: Timeouts) -> ServerResult<ScanEdClient> {
+        let base_url = parse_base_url(url)?;
+        let client = Client::builder()
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.request)
+            .build()
+            .map_err(ServerError::Unreachable)?;
+        let stream_client = Client::builder()
+            .connect_timeout(timeouts.connect)
+            .build()
+            .map_err(ServerError::Unreachable)?;
+        Ok(ScanEdClient { client, stream_client, base_url })
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// Asks the server for its version and features, falls back to the legacy protocol if
+    /// the server does not know the version endpoint.
+    pub async fn handshake(&self) -> ServerResult<ServerInfo> {
+        let server_info = match self.get_json::<ServerInfo>(VERSION_ENDPOINT).await {
+            Ok(server_info) => server_info,
+            Err(ServerError::ProtocolMismatch(_)) => {
+                info!("server has no version endpoint, using legacy protocol");
+                return Ok(ServerInfo::legacy());
+            }
+            Err(err) => return Err(err),
+        };
+        info!("server version: {:?}", server_info);
+        let compatible = server_info.major_version() == Some(SUPPORTED_PROTOCOL_VERSION)
+            && server_info.supports(ServerInfo::AUFTRAG)
+            && server_info.supports(ServerInfo::AUFNAHME);
+        if compatible {
+            Ok(server_info)
+        } else {
+            Err(ServerError::Incompatible(server_info.version))
+        }
+    }
+
+    pub async fn get_status(&self) -> ServerResult<AuftragStatus> {
+        self.get_json(AUFTRAG_ENPOINT).await
+    }
+
+    pub async fn post_auftrag(&self, auftrag: Auftrag) -> ServerResult<()> {
+        info!("post auftrag: {:?}", auftrag);
+        let response = self.client.post(self.endpoint(AUFTRAG_ENPOINT)?)
+            .json(&auftrag)
+            .send()
+            .await?;
+        check_status(AUFTRAG_ENPOINT, response.status())
+    }
+
+    pub async fn post_wiederholung(&self, wiederholung: Wiederholung) -> ServerResult<WiederholungAntwort> {
+        info!("post wiederholung: {:?}", wiederholung);
+        let response = self.client.post(self.endpoint(WIEDERHOLUNG_ENDPOINT)?)
+            .json(&wiederholung)
+            .send()
+            .await?;
+        check_status(WIEDERHOLUNG_ENDPOINT, response.status())?;
+        // older servers answer without a body
+        let body = response.bytes().await?;
+        Ok(serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    /// Asks the server to stop the turntable and the camera and drop the current Auftrag.
+    pub async fn post_abbruch(&self) -> ServerResult<()> {
+        info!("post abbruch");
+        let response = self.client.post(self.endpoint(ABBRUCH_ENDPOINT)?)
+            .send()
+            .await?;
+        check_status(ABBRUCH_ENDPOINT, response.status())
+    }
+
+    /// Halts the turntable and the camera after the current shot, the Auftrag is kept.
+    pub async fn post_pause(&self) -> ServerResult<()> {
+        info!("post pause");
+        let response = self.client.post(self.endpoint(PAUSE_ENDPOINT)?)
+            .send()
+            .await?;
+        check_status(PAUSE_ENDPOINT, response.status())
+    }
+
+    /// Continues a paused Auftrag.
+    pub async fn post_fortsetzen(&self) -> ServerResult<()> {
+        info!("post fortsetzen");
+        let response = self.client.post(self.endpoint(FORTSETZEN_ENDPOINT)?)
+            .send()
+            .await?;
+        check_status(FORTSETZEN_ENDPOINT, response.status())
+    }
+
+    /// Images which are ready for download in the order the server lists them, invalid paths and
+    /// images whose local name is already taken by another image are skipped.
+    pub async fn get_ready_image_list(&self) -> ServerResult<Vec<Aufnahme>> {
+        info!("requesting image index from server");
+        let server_paths = self.get_json::<Vec<String>>(AUFNAHMEN_ENDPOINT).await?;
+        let mut ids_by_name = HashMap::new();
+        Ok(server_paths.iter()
+            .filter_map(|server_path| match Aufnahme::from_server_path(server_path) {
+                Ok(aufnahme) => Some(aufnahme),
+                Err(err) => {
+                    warn!("{}", err);
+                    None
+                }
+            })
+            .filter(|aufnahme| match ids_by_name.entry(aufnahme.local_name.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(aufnahme.id.clone());
+                    true
+                }
+                Entry::Occupied(entry) => {
+                    if *entry.get() != aufnahme.id {
+                        warn!("skipping {}, its local name {} is taken by {}", aufnahme.id, aufnahme.local_name, entry.get());
+                    }
+                    false
+                }
+            })
+            .collect())
+    }
+
+    /// Requests an image starting at byte `offset`, so an interrupted transfer can be resumed.
+    pub async fn get_aufnahme(&self, aufnahme: &Aufnahme, offset: u64) -> ServerResult<AufnahmeDownload> {
+        info!("requesting image {} from server starting at byte {}", aufnahme.id, offset);
+        let mut url = self.endpoint(AUFNAHMEN_ENDPOINT)?;
+        url.path_segments_mut()
+            .map_err(|_| ServerError::InvalidUrl(self.base_url.to_string(), "cannot be a base".to_string()))?
+            .pop_if_empty()
+            .extend(aufnahme.id.split('/'));
+        let mut request = self.client.get(url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let response = request.send().await?;
+        // a 404 here means the image is missing, not the endpoint
+        if !response.status().is_success() {
+            return Err(ServerError::Status(response.status()));
+        }
+        // servers without range support answer with the whole image
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        if resumed {
+            let content_range = response.headers().get(CONTENT_RANGE)
+                .map_or("", |value| value.to_str().unwrap_or_default())
+                .to_string();
+            if range_start(&content_range) != Some(offset) {
+                return Err(ServerError::RangeMismatch(content_range, offset));
+            }
+        }
+        Ok(AufnahmeDownload { response, resumed })
+    }
+
+    /// Subscribes to the server-sent events announcing new images.
+    pub async fn subscribe_events(&self) -> ServerResult<EventStream> {
+        info!("subscribing to server events");
+        let response = self.stream_client.get(self.endpoint(EVENTS_ENDPOINT)?)
+            .header("Accept", "text/event-stream")
+            .send()
+            .await?;
+        check_status(EVENTS_ENDPOINT, response.status())?;
+        Ok(EventStream { response, buf: EventBuffer::default() })
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, endpoint: &str) -> ServerResult<T> {
+        let response = self.client.get(self.endpoint(endpoint)?).send().await?;
+        check_status(endpoint, response.status())?;
+        Ok(response.json::<T>().await?)
+    }
+
+    fn endpoint(&self, endpoint: &str) -> ServerResult<Url> {
+        self.base_url.join(endpoint)
+            .map_err(|err| ServerError::InvalidUrl(endpoint.to_string(), err.to_string()))
+    }
+}
+
+pub struct AufnahmeDownload {
+    response: Response,
+    pub resumed: bool,
+}
+
+impl AufnahmeDownload {
+    /// Next part of the image, None once the whole image was received.
+    pub async fn next_chunk(&mut self) -> ServerResult<Option<Bytes>> {
+        Ok(self.response.chunk().await?)
+    }
+}
+
+pub struct EventStream {
+    response: Response,
+    buf: EventBuffer,
+}
+
+impl EventStream {
+    /// Next event of the stream, None once the server closed it.
+    pub async fn next_event(&mut self) -> ServerResult<Option<AufnahmeEvent>> {
+        loop {
+            if let Some(event) = self.buf.next_event() {
+                return event.map(Some);
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buf.extend(&chunk),
+                None => return Ok(None),
             }
         }
     }
 }
 
-pub async fn get_status(url: &str) -> reqwest::Result<ServerStatus> {
-    reqwest::get(str_to_url(&url).join(AUFTRAG_ENPOINT).unwrap())
-        .await?
-        .json::<ServerStatus>().await
+/// Splits the received bytes into events, a chunk may end in the middle of a line or a character.
+#[derive(Default)]
+struct EventBuffer {
+    // bytes of an incomplete line, decoded once the line is complete
+    bytes: Vec<u8>,
+    // data lines of the event read so far
+    data: Vec<String>,
 }
 
-fn str_to_url(str: &str) -> Url {
-    reqwest::Url::from_str(&str).unwrap()
+impl EventBuffer {
+    fn extend(&mut self, chunk: &[u8]) {
+        self.bytes.extend_from_slice(chunk);
+    }
+
+    fn next_event(&mut self) -> Option<ServerResult<AufnahmeEvent>> {
+        while let Some(end) = self.bytes.iter().position(|byte| *byte == b'\n') {
+            let line = self.bytes.drain(..=end).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(&['\n', '\r'][..]);
+            if !line.is_empty() {
+                if let Some(data) = line.strip_prefix("data:") {
+                    self.data.push(data.trim().to_string());
+                }
+                continue;
+            }
+            // comments and keep-alives carry no data
+            if self.data.is_empty() {
+                continue;
+            }
+            let data = self.data.drain(..).collect::<Vec<_>>().join("\n");
+            return Some(serde_json::from_str(&data).map_err(ServerError::InvalidEvent));
+        }
+        None
+    }
 }
 
-pub async fn post_auftrag(auftrag: Auftrag, url: &str) -> Result<Response, Box<dyn Error + Send>> {
-    info!("post auftrag: {:?}", auftrag);
-    reqwest::Client::new().post(str_to_url(url)
-        .join(AUFTRAG_ENPOINT).map_err(|err| -> Box<dyn Error + Send> { Box::new(err) })?)
-        .json(&auftrag)
-        .send()
-        .await
-        .map_err(|err| -> Box<dyn Error + Send> { Box::new(err) })
+// a missing endpoint means the server speaks a different version of the protocol, only for
+// requests to the endpoint itself and not to resources below it
+fn check_status(endpoint: &str, status: StatusCode) -> ServerResult<()> {
+    match status {
+        status if status.is_success() => Ok(()),
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED =>
+            Err(ServerError::ProtocolMismatch(endpoint.to_string())),
+        status => Err(ServerError::Status(status)),
+    }
 }
 
-pub(crate) async fn get_ready_image_list(url: &str) -> reqwest::Result<HashSet<String>> {
-    info!("requesting image index from server");
-    Ok(reqwest::get(str_to_url(url).join(AUFNAHMEN_ENDPOINT).unwrap())
-        .await?
-        .json::<HashSet<String>>().await.unwrap())
+// first byte of a content range like "bytes 100-199/200"
+fn range_start(content_range: &str) -> Option<u64> {
+    let range = content_range.strip_prefix("bytes ")?;
+    range[..range.find('-')?].trim().parse().ok()
 }
 
-pub(crate) async fn get_aufnahme(url: &str, img_path: &String) -> reqwest::Result<Vec<u8>> {
-    info!("requesting image from server");
-    let response = reqwest::get(str_to_url(url)
-        .join(AUFNAHMEN_ENDPOINT).unwrap()
-        .join(&img_path).unwrap())
-        .await?
-        .bytes().await?.to_vec();
+pub fn parse_base_url(url: &str) -> ServerResult<Url> {
+    let mut base_url = Url::parse(url)
+        .map_err(|err| ServerError::InvalidUrl(url.to_string(), err.to_string()))?;
+    if !matches!(base_url.scheme(), "http" | "https") || base_url.cannot_be_a_base() {
+        return Err(ServerError::InvalidUrl(url.to_string(), "expected a http(s) url".to_string()));
+    }
+    // endpoints are joined relative to the base path
+    if !base_url.path().ends_with('/') {
+        let path = format!("{}/", base_url.path());
+        base_url.set_path(&path);
+    }
+    Ok(base_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::com_model::Aufnahme;
+    use super::{range_start, EventBuffer};
+
+    fn local_name(server_path: &str) -> String {
+        Aufnahme::from_server_path(server_path).unwrap().local_name
+    }
+
+    #[test]
+    fn plain_file_names_are_kept() {
+        assert_eq!(local_name("/aufnahme/img_3.jpg"), "img_3.jpg");
+        assert_eq!(local_name("img-3.jpg"), "img-3.jpg");
+    }
+
+    #[test]
+    fn different_ids_get_different_local_names() {
+        let names = ["a/b.jpg", "a_b.jpg", "a b.jpg", "a/b/c.jpg", "a_b/c.jpg", "a/b_c.jpg", "/aufnahme/.b.jpg", "_b.jpg"]
+            .iter()
+            .map(|path| local_name(path))
+            .collect::<Vec<_>>();
+        for (index, name) in names.iter().enumerate() {
+            assert!(!names[index + 1..].contains(name), "{} is not unique in {:?}", name, names);
+            assert!(!name.starts_with('.') && !name.contains('/'), "{} is no safe file name", name);
+        }
+        assert!(local_name("runde1/img_3.jpg").ends_with(".jpg"));
+    }
 
-    Ok(response)
-}
\ No newline at end of file
+    #[test]
+    fn escaping_paths_are_rejected() {
+        for path in ["../b.jpg", "a//b.jpg", "a/./b.jpg", "a\\b.jpg", ""].iter() {
+            assert!(Aufnahme::from_server_path(path).is_err(), "{} was accepted", path);
+        }
+    }
+
+    #[test]
+    fn start_of_content_ranges() {
+        assert_eq!(range_start("bytes 100-199/200"), Some(100));
+        assert_eq!(range_start("bytes 0-199/*"), Some(0));
+        assert_eq!(range_start("bytes */200"), None);
+        assert_eq!(range_start("items 100-199/200"), None);
+    }
+
+    #[test]
+    fn events_split_across_chunks() {
+        let stream = ": keep-alive\r\n\r\ndata: {\"path\": \"runde1/bild_ä.jpg\",\r\ndata: \"runde\": 1, \"aufnahme\": 2}\r\n\r\n"
+            .as_bytes();
+        // every split point, also inside the two bytes of the umlaut and between \r and \n
+        for split in 0..stream.len() {
+            let mut buf = EventBuffer::default();
+            buf.extend(&stream[..split]);
+            let mut events = Vec::new();
+            events.extend(buf.next_event());
+            buf.extend(&stream[split..]);
+            events.extend(buf.next_event());
+            assert!(buf.next_event().is_none());
+            assert_eq!(events.len(), 1, "split at {}", split);
+            let event = events.pop().unwrap().unwrap();
+            assert_eq!((event.path.as_str(), event.runde, event.aufnahme), ("runde1/bild_ä.jpg", 1, 2));
+        }
+    }
+}
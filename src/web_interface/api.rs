@@ -0,0 +1,445 @@
+use actix_web::{Responder, web, get, guard, HttpRequest, HttpResponse};
+use actix_web::dev::HttpServiceFactory;
+use actix_web::error::InternalError;
+use actix_web::http::Method;
+use crate::AppData;
+use crate::web_interface::app_state::{self, error_response};
+use crate::web_interface::model::{Order, OrderInfo, ThumbnailQuery, LogQuery, ReconstructionQuery, ReconstructionLog, ErrorKind,
+                                  Credentials, ImageEntry, ImagePhaseStatus, ResetReport, SessionInfo};
+use crate::web_interface::auth::{self, Authenticator, SessionEntry, TokenInfo};
+use crate::web_interface::openapi::{self, SpecBuilder, ok, empty, binary, image_name};
+use crate::web_interface::templates::TemplateRegistry;
+use crate::photogrammetry::image_handling::ImageVersion;
+use crate::photogrammetry::metadata::ImageMetadata;
+use crate::photogrammetry::photogrammetry::ReconstructionProgress;
+use crate::photogrammetry::quality::QualityReport;
+use crate::photogrammetry::thumbnails::ThumbnailSize;
+use log::{info, error};
+use serde_json::{json, Value};
+
+pub const PREFIX: &str = "/api/v1";
+// full paths of the routes the authentication treats differently
+pub const SESSION_PATH: &str = "/api/v1/session";
+pub const TOKEN_PATH: &str = "/api/v1/auth/token";
+pub const AUTH_SESSIONS_PATH: &str = "/api/v1/auth/sessions";
+
+/// Route of a routing table, the table is registered and documented in the openapi spec
+/// from the same entries.
+pub struct Route {
+    pub method: Method,
+    // below the scope the table is registered in
+    pub path: &'static str,
+    // name of the handler, the operation id in the spec
+    pub handler: &'static str,
+    pub summary: &'static str,
+    pub to: fn(actix_web::Route) -> actix_web::Route,
+    // parameters, body and responses, the error response is added to every operation
+    pub operation: fn(&mut SpecBuilder) -> Value,
+}
+
+impl Route {
+    pub fn service(&self) -> impl HttpServiceFactory {
+        web::resource(self.path)
+            .name(self.handler)
+            .guard(guard::Method(self.method.clone()))
+            .route((self.to)(web::route()))
+    }
+}
+
+/// Every resource of the json api, below `PREFIX`.
+pub const ROUTES: &[Route] = &[
+    Route {
+        method: Method::GET, path: "/session", handler: "get_session", summary: "Aktuelle Phase",
+        to: |route| route.to(get_session),
+        operation: |spec| json!({ "responses": { "200": ok("Phase des Clients", spec.json_content::<SessionInfo>()) } }),
+    },
+    Route {
+        method: Method::DELETE, path: "/session", handler: "delete_session", summary: "Abbrechen und zurück zur Konfiguration",
+        to: |route| route.to(delete_session),
+        operation: |spec| json!({
+            "responses": {
+                "200": ok("Ergebnis des Abbruchs, nach Photogrammetrie oder Modell ist nichts mehr zu stoppen",
+                          spec.json_content::<ResetReport>()),
+            },
+        }),
+    },
+    Route {
+        method: Method::GET, path: "/order", handler: "get_order", summary: "Laufender Auftrag",
+        to: |route| route.to(get_order),
+        operation: |spec| json!({ "responses": { "200": ok("Auftrag", spec.json_content::<OrderInfo>()) } }),
+    },
+    Route {
+        method: Method::POST, path: "/order", handler: "post_order", summary: "Auftrag an den ScanEd-Server senden",
+        to: |route| route.to(post_order),
+        operation: |spec| json!({
+            "requestBody": { "required": true, "content": spec.json_content::<Order>() },
+            "responses": { "201": ok("Auftrag gestartet, die Aufnahme läuft", spec.json_content::<OrderInfo>()) },
+        }),
+    },
+    Route {
+        method: Method::GET, path: "/capture/status", handler: "get_capture_status", summary: "Status der Aufnahme und der Downloads",
+        to: |route| route.to(get_capture_status),
+        operation: |spec| json!({ "responses": { "200": ok("Status", spec.json_content::<ImagePhaseStatus>()) } }),
+    },
+    Route {
+        method: Method::POST, path: "/capture/pause", handler: "pause_capture", summary: "Auftrag pausieren",
+        to: |route| route.to(pause_capture),
+        operation: |_| json!({ "responses": { "204": empty("Pausiert") } }),
+    },
+    Route {
+        method: Method::POST, path: "/capture/resume", handler: "resume_capture", summary: "Auftrag fortsetzen",
+        to: |route| route.to(resume_capture),
+        operation: |_| json!({ "responses": { "204": empty("Fortgesetzt") } }),
+    },
+    Route {
+        method: Method::GET, path: "/images", handler: "get_images", summary: "Heruntergeladene und ausgeschlossene Aufnahmen",
+        to: |route| route.to(get_images),
+        operation: |spec| json!({ "responses": { "200": ok("Aufnahmen", spec.json_content::<Vec<ImageEntry>>()) } }),
+    },
+    // registered before the image names they would match otherwise
+    Route {
+        method: Method::GET, path: "/images/quality", handler: "get_image_quality", summary: "Qualität der Aufnahmen",
+        to: |route| route.to(get_image_quality),
+        operation: |spec| json!({ "responses": { "200": ok("Qualitätsbericht", spec.json_content::<QualityReport>()) } }),
+    },
+    Route {
+        method: Method::GET, path: "/images/metadata", handler: "get_image_metadata", summary: "Metadaten aller Aufnahmen",
+        to: |route| route.to(get_image_metadata),
+        operation: |spec| json!({ "responses": { "200": ok("Metadaten", spec.json_content::<Vec<ImageMetadata>>()) } }),
+    },
+    Route {
+        method: Method::GET, path: "/images/{image_name}", handler: "get_image", summary: "Aufnahme",
+        to: |route| route.to(get_image),
+        operation: |_| json!({
+            "parameters": [image_name()],
+            "responses": { "200": ok("Bilddatei", binary("application/octet-stream")) },
+        }),
+    },
+    Route {
+        method: Method::GET, path: "/images/{image_name}/thumbnail", handler: "get_image_thumbnail", summary: "Vorschaubild",
+        to: |route| route.to(get_image_thumbnail),
+        operation: |spec| json!({
+            "parameters": [
+                image_name(),
+                { "name": "size", "in": "query", "required": false, "schema": spec.schema::<ThumbnailSize>() },
+            ],
+            "responses": { "200": ok("JPEG, standardmäßig small", binary("image/jpeg")) },
+        }),
+    },
+    Route {
+        method: Method::GET, path: "/images/{image_name}/metadata", handler: "get_specific_image_metadata",
+        summary: "Metadaten einer Aufnahme",
+        to: |route| route.to(get_specific_image_metadata),
+        operation: |spec| json!({
+            "parameters": [image_name()],
+            "responses": { "200": ok("Metadaten", spec.json_content::<ImageMetadata>()) },
+        }),
+    },
+    Route {
+        method: Method::GET, path: "/images/{image_name}/versions", handler: "get_image_versions",
+        summary: "Ersetzte Versionen einer Aufnahme",
+        to: |route| route.to(get_image_versions),
+        operation: |spec| json!({
+            "parameters": [image_name()],
+            "responses": { "200": ok("Versionen", spec.json_content::<Vec<ImageVersion>>()) },
+        }),
+    },
+    Route {
+        method: Method::POST, path: "/images/{image_name}/exclude", handler: "exclude_image",
+        summary: "Aufnahme von der Photogrammetrie ausschließen",
+        to: |route| route.to(exclude_image),
+        operation: |_| json!({ "parameters": [image_name()], "responses": { "204": empty("Ausgeschlossen") } }),
+    },
+    Route {
+        method: Method::DELETE, path: "/images/{image_name}/exclude", handler: "restore_image",
+        summary: "Ausgeschlossene Aufnahme wiederherstellen",
+        to: |route| route.to(restore_image),
+        operation: |_| json!({ "parameters": [image_name()], "responses": { "204": empty("Wiederhergestellt") } }),
+    },
+    Route {
+        method: Method::POST, path: "/images/{image_name}/recapture", handler: "recapture_image", summary: "Aufnahme wiederholen",
+        to: |route| route.to(recapture_image),
+        operation: |_| json!({
+            "parameters": [image_name()],
+            "responses": { "202": empty("Der Server nimmt das Bild neu auf") },
+        }),
+    },
+    Route {
+        method: Method::POST, path: "/reconstruction", handler: "start_reconstruction", summary: "Photogrammetrie starten",
+        to: |route| route.to(start_reconstruction),
+        operation: |_| json!({
+            "parameters": [
+                { "name": "force", "in": "query", "required": false, "schema": { "type": "boolean", "default": false },
+                  "description": "auch starten, wenn die Qualitätsprüfung NotReady meldet" },
+            ],
+            "responses": { "202": empty("OpenDroneMap läuft") },
+        }),
+    },
+    Route {
+        method: Method::GET, path: "/reconstruction/log", handler: "get_reconstruction_log", summary: "Ausgabe von OpenDroneMap",
+        to: |route| route.to(get_reconstruction_log),
+        operation: |spec| json!({
+            "parameters": [
+                { "name": "from", "in": "query", "required": false, "schema": { "type": "integer", "minimum": 0 } },
+            ],
+            "responses": {
+                "200": ok("Zeilen ab from, next ist der Index der nächsten Zeile", spec.json_content::<ReconstructionLog>()),
+            },
+        }),
+    },
+    Route {
+        method: Method::GET, path: "/reconstruction/progress", handler: "get_reconstruction_progress",
+        summary: "Fortschritt der Photogrammetrie",
+        to: |route| route.to(get_reconstruction_progress),
+        operation: |spec| json!({ "responses": { "200": ok("Fortschritt", spec.json_content::<ReconstructionProgress>()) } }),
+    },
+    Route {
+        method: Method::GET, path: "/model", handler: "get_model", summary: "3D-Modell herunterladen",
+        to: |route| route.to(get_model),
+        operation: |_| json!({ "responses": { "200": ok("Texturiertes Modell", binary("application/zip")) } }),
+    },
+    Route {
+        method: Method::POST, path: "/model", handler: "finish_reconstruction", summary: "Photogrammetrie abschließen",
+        to: |route| route.to(finish_reconstruction),
+        operation: |_| json!({ "responses": { "204": empty("Das Modell steht bereit") } }),
+    },
+    Route {
+        method: Method::POST, path: "/auth/token", handler: "post_token", summary: "Anmelden und Token erhalten",
+        to: |route| route.to(post_token),
+        operation: |spec| json!({
+            "requestBody": { "required": true, "content": spec.json_content::<Credentials>() },
+            "responses": {
+                "201": ok("Token für den Authorization-Header, gültig bis zur Abmeldung", spec.json_content::<TokenInfo>()),
+            },
+            "security": [],
+        }),
+    },
+    Route {
+        method: Method::DELETE, path: "/auth/token", handler: "delete_token", summary: "Abmelden",
+        to: |route| route.to(delete_token),
+        operation: |_| json!({ "responses": { "204": empty("Das Token ist ungültig") } }),
+    },
+    Route {
+        method: Method::GET, path: "/auth/sessions", handler: "get_auth_sessions", summary: "Angemeldete Benutzer",
+        to: |route| route.to(get_auth_sessions),
+        operation: |spec| json!({
+            "responses": { "200": ok("Sitzungen, nur für Admins", spec.json_content::<Vec<SessionEntry>>()) },
+        }),
+    },
+    Route {
+        method: Method::DELETE, path: "/auth/sessions", handler: "delete_auth_sessions", summary: "Alle Benutzer abmelden",
+        to: |route| route.to(delete_auth_sessions),
+        operation: |_| json!({ "responses": { "204": empty("Alle Sitzungen beendet, nur für Admins") } }),
+    },
+];
+
+/// Registers the json api, which offers every resource of the web interface without the
+/// html pages. Errors are answered with an `ErrorBody`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_openapi_spec);
+    cfg.service(get_api_docs);
+    let mut scope = web::scope(PREFIX)
+        .app_data(web::JsonConfig::default().error_handler(|err, _| bad_request(err)))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| bad_request(err)));
+    for route in ROUTES {
+        scope = scope.service(route.service());
+    }
+    cfg.service(scope.default_service(web::route().to(unknown_endpoint)));
+}
+
+// invalid json bodies and queries are answered like every other error
+fn bad_request<E: std::fmt::Display>(err: E) -> actix_web::Error {
+    let message = err.to_string();
+    InternalError::from_response(message.clone(), error_response(ErrorKind::BadRequest, message)).into()
+}
+
+async fn unknown_endpoint(req: HttpRequest) -> HttpResponse {
+    error_response(ErrorKind::NotFound, format!("no endpoint {} {}", req.method(), req.path()))
+}
+
+#[get("/api/openapi.json")]
+pub(crate) async fn get_openapi_spec() -> impl Responder {
+    info!("serving openapi spec");
+    HttpResponse::Ok().json(openapi::spec())
+}
+
+#[get("/api/docs")]
+pub(crate) async fn get_api_docs(templates: web::Data<TemplateRegistry>) -> impl Responder {
+    info!("serving api docs");
+    match templates.render_api_docs(openapi::SPEC_PATH) {
+        Ok(html) => HttpResponse::Ok().content_type("text/html; charset=utf-8").body(html),
+        Err(err) => {
+            error!("unable to render api docs: {}", err);
+            error_response(ErrorKind::Internal, err.to_string())
+        }
+    }
+}
+
+pub(crate) async fn get_session(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api session");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().session().await
+}
+
+pub(crate) async fn delete_session(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api session reset");
+    app_state::reset(&data.app_state).await
+}
+
+pub(crate) async fn get_order(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api order");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().order().await
+}
+
+pub(crate) async fn post_order(data: web::Data<AppData>, order: web::Json<Order>) -> impl Responder {
+    info!("serving api order post");
+    let mut app_state = data.app_state.lock().await;
+    let (new_app_state, res) = app_state.take().unwrap().post_order(order.into_inner()).await;
+    *app_state = Some(new_app_state);
+    res
+}
+
+pub(crate) async fn get_capture_status(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api capture status");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().status().await
+}
+
+pub(crate) async fn pause_capture(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api capture pause");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().pause().await
+}
+
+pub(crate) async fn resume_capture(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api capture resume");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().resume().await
+}
+
+pub(crate) async fn get_images(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api image list");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().get_image_list().await
+}
+
+pub(crate) async fn get_image_quality(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api image quality report");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().get_quality_report().await
+}
+
+pub(crate) async fn get_image_metadata(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api image metadata index");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().get_metadata().await
+}
+
+pub(crate) async fn get_image(data: web::Data<AppData>, web::Path(image_name): web::Path<String>) -> impl Responder {
+    info!("serving api image");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().get_specific_content(&image_name).await
+}
+
+pub(crate) async fn get_image_thumbnail(data: web::Data<AppData>, web::Path(image_name): web::Path<String>,
+                                        query: web::Query<ThumbnailQuery>) -> impl Responder {
+    info!("serving api thumbnail");
+    let app_state = data.app_state.lock().await;
+    let size = query.size.unwrap_or(ThumbnailSize::Small);
+    app_state.as_ref().unwrap().get_thumbnail(&image_name, size).await
+}
+
+pub(crate) async fn get_specific_image_metadata(data: web::Data<AppData>,
+                                                web::Path(image_name): web::Path<String>) -> impl Responder {
+    info!("serving api image metadata");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().get_specific_metadata(&image_name).await
+}
+
+pub(crate) async fn get_image_versions(data: web::Data<AppData>, web::Path(image_name): web::Path<String>) -> impl Responder {
+    info!("serving api image versions");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().get_image_versions(&image_name).await
+}
+
+pub(crate) async fn exclude_image(data: web::Data<AppData>, web::Path(image_name): web::Path<String>) -> impl Responder {
+    info!("serving api exclude image");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().set_image_excluded(&image_name, true).await
+}
+
+pub(crate) async fn restore_image(data: web::Data<AppData>, web::Path(image_name): web::Path<String>) -> impl Responder {
+    info!("serving api restore image");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().set_image_excluded(&image_name, false).await
+}
+
+pub(crate) async fn recapture_image(data: web::Data<AppData>, web::Path(image_name): web::Path<String>) -> impl Responder {
+    info!("serving api recapture image");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().request_recapture(&image_name).await
+}
+
+pub(crate) async fn start_reconstruction(data: web::Data<AppData>, query: web::Query<ReconstructionQuery>) -> impl Responder {
+    info!("serving api reconstruction start");
+    let mut app_state = data.app_state.lock().await;
+    let (new_app_state, res) = app_state.take().unwrap().start_reconstruction(query.force).await;
+    *app_state = Some(new_app_state);
+    res
+}
+
+pub(crate) async fn get_reconstruction_log(data: web::Data<AppData>, query: web::Query<LogQuery>) -> impl Responder {
+    info!("serving api reconstruction log");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().reconstruction_log(query.from.unwrap_or(0)).await
+}
+
+pub(crate) async fn get_reconstruction_progress(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api reconstruction progress");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().reconstruction_progress().await
+}
+
+pub(crate) async fn get_model(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api model");
+    let app_state = data.app_state.lock().await;
+    app_state.as_ref().unwrap().get_model().await
+}
+
+// the reconstruction is done, its result becomes the model
+pub(crate) async fn finish_reconstruction(data: web::Data<AppData>) -> impl Responder {
+    info!("serving api reconstruction finish");
+    let mut app_state = data.app_state.lock().await;
+    let (new_app_state, res) = app_state.take().unwrap().finish_reconstruction().await;
+    *app_state = Some(new_app_state);
+    res
+}
+
+pub(crate) async fn post_token(authenticator: web::Data<Authenticator>, credentials: web::Json<Credentials>) -> impl Responder {
+    info!("serving api token request");
+    match auth::login(authenticator, credentials.into_inner()).await {
+        Ok(token_info) => HttpResponse::Created().json(token_info),
+        Err(res) => res,
+    }
+}
+
+pub(crate) async fn delete_token(authenticator: web::Data<Authenticator>, req: HttpRequest) -> impl Responder {
+    info!("serving api token revocation");
+    if let Some(token) = auth::request_token(&req) {
+        authenticator.end_session(&token);
+    }
+    HttpResponse::NoContent().finish()
+}
+
+pub(crate) async fn get_auth_sessions(authenticator: web::Data<Authenticator>) -> impl Responder {
+    info!("serving api auth sessions");
+    HttpResponse::Ok().json(authenticator.sessions())
+}
+
+pub(crate) async fn delete_auth_sessions(authenticator: web::Data<Authenticator>) -> impl Responder {
+    info!("serving api auth sessions deletion");
+    let count = authenticator.end_all_sessions();
+    info!("ended {} sessions", count);
+    HttpResponse::NoContent().finish()
+}
@@ -1,57 +1,125 @@
 use actix_web::{HttpResponse, HttpRequest, web};
-use crate::web_interface::model::{PageForm, NotificationHandle};
-use std::fs;
+use crate::web_interface::model::{PageForm, NotificationHandle, ImagePhaseStatus, Order, OrderInfo, Phase, SessionInfo,
+                                  ImageEntry, ReconstructionLog, ErrorKind, ErrorBody, ResetReport};
+use std::io;
+use crate::web_interface::templates::{PageView, StartupContext, ImagePhaseContext, PhotogrammetryContext, ModelContext};
 use std::sync::{Arc, Mutex};
 use actix_web_actors::ws;
 use crate::web_interface::model::ws::{MyWs};
 use actix::{Addr};
-use crate::photogrammetry::image_handling::{ImageDownloader};
+use crate::photogrammetry::image_handling::ImageDownloader;
 use async_trait::async_trait;
-use std::ops::Deref;
-use crate::server_com::{com_model};
+use crate::server_com::ServerError;
+use crate::config::ScanSettings;
 use actix_web::web::Payload;
-use crate::photogrammetry::photogrammetry::start_photogrammetry;
-use serde::Serialize;
+use crate::photogrammetry::photogrammetry::{self, start_photogrammetry};
+use crate::photogrammetry::paths;
 use std::error::Error;
-use crate::server_com;
 use log::{warn};
+use crate::photogrammetry::thumbnails::ThumbnailSize;
+use crate::photogrammetry::quality::Readiness;
+use futures::future::{self, BoxFuture, FutureExt, Shared};
+use crate::livestream;
 
 mod constants {
-    pub const CONTENT: &'static str = "media_content";
+    pub const API_IMAGES: &str = "/api/v1/images";
 }
 
-#[derive(Serialize)]
-struct MasterTemplateContext {
-    page_content: String
-}
+/// Stops what the previous phase left running, awaited after the new phase took over.
+pub type ResetCleanup = Shared<BoxFuture<'static, ResetReport>>;
 
 #[async_trait]
 pub trait AppState {
-    async fn index(&self) -> HttpResponse;
+    async fn page_view(&self) -> PageView;
+    async fn session(&self) -> HttpResponse;
     async fn status(&self) -> HttpResponse;
-    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse);
+    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, Result<ResetCleanup, HttpResponse>);
     async fn post_page_form(self: Box<Self>, page_form: PageForm) -> (Box<dyn AppState + Sync + Send>, HttpResponse);
-    async fn get_content(&self) -> HttpResponse;
+    async fn post_order(self: Box<Self>, order: Order) -> (Box<dyn AppState + Sync + Send>, HttpResponse);
+    async fn order(&self) -> HttpResponse;
+    async fn start_reconstruction(self: Box<Self>, force: bool) -> (Box<dyn AppState + Sync + Send>, HttpResponse);
+    async fn finish_reconstruction(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse);
+    async fn get_image_list(&self) -> HttpResponse;
     async fn get_specific_content(&self, name: &str) -> HttpResponse;
+    async fn get_thumbnail(&self, name: &str, size: ThumbnailSize) -> HttpResponse;
+    async fn get_metadata(&self) -> HttpResponse;
+    async fn get_specific_metadata(&self, name: &str) -> HttpResponse;
+    async fn get_quality_report(&self) -> HttpResponse;
+    async fn set_image_excluded(&self, name: &str, exclude: bool) -> HttpResponse;
+    async fn request_recapture(&self, name: &str) -> HttpResponse;
+    async fn get_image_versions(&self, name: &str) -> HttpResponse;
+    async fn pause(&self) -> HttpResponse;
+    async fn resume(&self) -> HttpResponse;
+    async fn reconstruction_log(&self, from: usize) -> HttpResponse;
+    async fn reconstruction_progress(&self) -> HttpResponse;
+    async fn get_model(&self) -> HttpResponse;
     fn ws_notification(&self, req: HttpRequest, stream: web::Payload) -> HttpResponse;
+    fn livestream_url(&self) -> Option<reqwest::Url>;
+}
+
+pub(crate) fn error_response(kind: ErrorKind, message: impl Into<String>) -> HttpResponse {
+    let mut res = match kind {
+        ErrorKind::BadRequest => HttpResponse::BadRequest(),
+        ErrorKind::Unauthorized => HttpResponse::Unauthorized(),
+        ErrorKind::Forbidden => HttpResponse::Forbidden(),
+        ErrorKind::NotFound => HttpResponse::NotFound(),
+        ErrorKind::WrongPhase | ErrorKind::InvalidState => HttpResponse::Conflict(),
+        ErrorKind::ServerError => HttpResponse::BadGateway(),
+        ErrorKind::ServerTimeout => HttpResponse::GatewayTimeout(),
+        ErrorKind::Internal => HttpResponse::InternalServerError(),
+    };
+    res.json(ErrorBody { error: kind, message: message.into() })
 }
 
-fn render_master_page(html: String) -> String {
-    let mut tt = tinytemplate::TinyTemplate::new();
-    let master_template = std::fs::read_to_string("html/master.html").unwrap();
-    tt.add_template("master", &master_template).unwrap();
-    tt.render("master", &MasterTemplateContext { page_content: html }).unwrap()
+/// Resets to the configuration and answers with the report once the previous phase stopped.
+/// The app state is only locked to swap the phase, the cleanup may wait for the server.
+pub(crate) async fn reset(app_state: &tokio::sync::Mutex<Option<Box<dyn AppState + Send>>>) -> HttpResponse {
+    let cleanup = {
+        let mut app_state = app_state.lock().await;
+        let (new_app_state, cleanup) = app_state.take().unwrap().reset().await;
+        *app_state = Some(new_app_state);
+        cleanup
+    };
+    match cleanup {
+        Ok(cleanup) => HttpResponse::Ok().json(cleanup.await),
+        Err(res) => res,
+    }
 }
 
-fn render_page(path: &str) -> HttpResponse {
-    let page_html = fs::read_to_string(path).unwrap();
-    let rendered_html = render_master_page(page_html);
-    HttpResponse::Ok().body(rendered_html)
+fn idle_cleanup() -> ResetCleanup {
+    future::ready(ResetReport::idle()).boxed().shared()
+}
+
+fn wrong_phase_response(endpoint: &str, phase: &str) -> HttpResponse {
+    error_response(ErrorKind::WrongPhase, format!("{} not available in the phase {}", endpoint, phase))
+}
+
+fn image_response(image: Result<Vec<u8>, Option<tokio::io::Error>>, content_type: Option<&str>) -> HttpResponse {
+    match image {
+        Ok(img) => {
+            let mut res = HttpResponse::Ok();
+            if let Some(content_type) = content_type {
+                res.header("Content-Type", content_type);
+            }
+            res.body(actix_web::web::Bytes::from(img))
+        }
+        Err(None) => {
+            // image not found in image store
+            error_response(ErrorKind::NotFound, "image not found")
+        }
+        Err(Some(io_error)) => {
+            // error accessing image
+            error_response(ErrorKind::Internal, io_error.to_string())
+        }
+    }
 }
 
-fn endpoint_not_found_in_phase(endpoint: &str, phase: &str) -> HttpResponse {
-    HttpResponse::NotFound()
-        .body(format!("{} not implemented for the Phase {}", endpoint, phase))
+pub(crate) fn server_error_response(err: &ServerError) -> HttpResponse {
+    match err {
+        ServerError::InvalidUrl(_, _) => error_response(ErrorKind::BadRequest, err.to_string()),
+        ServerError::Timeout => error_response(ErrorKind::ServerTimeout, err.to_string()),
+        _ => error_response(ErrorKind::ServerError, err.to_string()),
+    }
 }
 
 fn redirect_response(path: &str) -> HttpResponse {
@@ -59,212 +127,511 @@ fn redirect_response(path: &str) -> HttpResponse {
         header("location", path).finish()
 }
 
+// the html pages continue on the page of the new phase
+fn redirect_on_success(res: HttpResponse) -> HttpResponse {
+    if res.status().is_success() {
+        redirect_response("/")
+    } else {
+        res
+    }
+}
+
 #[derive(Clone)]
-pub struct Start {}
+pub struct Start {
+    settings: ScanSettings,
+    // offered again on the startup page after a reset
+    server_url: Option<String>,
+    // cancelling of the previous Auftrag, a new one is only started after it finished
+    previous_scan: Option<ResetCleanup>,
+}
+
+impl Start {
+    pub fn new(settings: ScanSettings) -> Start {
+        Start { settings, server_url: None, previous_scan: None }
+    }
+}
 
 #[async_trait]
 impl AppState for Start {
-    async fn index(&self) -> HttpResponse {
-        render_page("html/startup_page.html")
+    async fn page_view(&self) -> PageView {
+        PageView::Startup(StartupContext { server_url: self.server_url.clone() })
+    }
+
+    async fn session(&self) -> HttpResponse {
+        HttpResponse::Ok().json(SessionInfo { phase: Phase::Configuration, server_url: self.server_url.clone() })
     }
 
     async fn status(&self) -> HttpResponse {
-        endpoint_not_found_in_phase("/status(get)", "Configuration")
+        wrong_phase_response("capture/status", "Configuration")
     }
 
-    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
-        (self, endpoint_not_found_in_phase("/(delete)", "Configuration"))
+    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, Result<ResetCleanup, HttpResponse>) {
+        (self, Err(wrong_phase_response("session(delete)", "Configuration")))
     }
 
     async fn post_page_form(self: Box<Self>, page_form: PageForm) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
-        let auftrag = if let PageForm::Auftrag(auftrag) = page_form {
-            auftrag
-        } else {
-            return (
-                self,
-                HttpResponse::InternalServerError().body("Invalid Form was Submitted")
-            );
+        let auftrag = match page_form {
+            PageForm::Auftrag(auftrag) => auftrag,
+            page_form => return (self, wrong_phase_response(page_form.action(), "Configuration")),
         };
 
         // parse form
-        let rounds = match auftrag.clone().into_vec() {
-            Ok(rounds) => rounds,
+        let order = match auftrag.into_order() {
+            Ok(order) => order,
             Err(err) => {
-                return (self, HttpResponse::InternalServerError().body(err.to_string()));
+                return (self, error_response(ErrorKind::BadRequest, err.to_string()));
             }
         };
 
+        let (app_state, res) = self.post_order(order).await;
+        (app_state, redirect_on_success(res))
+    }
+
+    async fn post_order(self: Box<Self>, order: Order) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        if let Err(message) = order.validate() {
+            return (self, error_response(ErrorKind::BadRequest, message));
+        }
+
+        if self.previous_scan.as_ref().is_some_and(|previous_scan| previous_scan.peek().is_none()) {
+            return (self, error_response(ErrorKind::InvalidState, "the previous Auftrag is still being cancelled"));
+        }
+
         // if initializing folder or post request to server fails return error
-        let image_phase = match ImagePhase::new(
-            auftrag.get_url().to_string(),
-            rounds,
-        ).await {
+        let image_phase = match ImagePhase::new(order, self.settings).await {
             Ok(image_phase) => image_phase,
             Err(err) => {
-                return (self, HttpResponse::InternalServerError().body(err.to_string()));
+                let res = match err.downcast_ref::<ServerError>() {
+                    Some(server_error) => server_error_response(server_error),
+                    None => error_response(ErrorKind::Internal, err.to_string()),
+                };
+                return (self, res);
             }
         };
 
-        (
-            Box::new(image_phase),
-            redirect_response("/")
-        )
+        let res = HttpResponse::Created().json(image_phase.order_info());
+        (Box::new(image_phase), res)
     }
 
-    async fn get_content(&self) -> HttpResponse {
-        endpoint_not_found_in_phase("media_content", "Configuration")
+    async fn order(&self) -> HttpResponse {
+        wrong_phase_response("order", "Configuration")
+    }
+
+    async fn start_reconstruction(self: Box<Self>, _force: bool) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, wrong_phase_response("reconstruction(post)", "Configuration"))
+    }
+
+    async fn finish_reconstruction(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, wrong_phase_response("model(post)", "Configuration"))
+    }
+
+    async fn get_image_list(&self) -> HttpResponse {
+        wrong_phase_response("images", "Configuration")
     }
 
     async fn get_specific_content(&self, _name: &str) -> HttpResponse {
-        endpoint_not_found_in_phase("media_content/{content_name}", "Configuration")
+        wrong_phase_response("images/{image_name}", "Configuration")
+    }
+
+    async fn get_thumbnail(&self, _name: &str, _size: ThumbnailSize) -> HttpResponse {
+        wrong_phase_response("images/{image_name}/thumbnail", "Configuration")
+    }
+
+    async fn get_metadata(&self) -> HttpResponse {
+        wrong_phase_response("images/metadata", "Configuration")
+    }
+
+    async fn get_specific_metadata(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("images/{image_name}/metadata", "Configuration")
+    }
+
+    async fn get_quality_report(&self) -> HttpResponse {
+        wrong_phase_response("images/quality", "Configuration")
+    }
+
+    async fn set_image_excluded(&self, _name: &str, _exclude: bool) -> HttpResponse {
+        wrong_phase_response("images/{image_name}/exclude", "Configuration")
+    }
+
+    async fn request_recapture(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("images/{image_name}/recapture", "Configuration")
+    }
+
+    async fn get_image_versions(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("images/{image_name}/versions", "Configuration")
+    }
+
+    async fn pause(&self) -> HttpResponse {
+        wrong_phase_response("pause", "Configuration")
+    }
+
+    async fn resume(&self) -> HttpResponse {
+        wrong_phase_response("resume", "Configuration")
+    }
+
+    async fn reconstruction_log(&self, _from: usize) -> HttpResponse {
+        wrong_phase_response("reconstruction/log", "Configuration")
+    }
+
+    async fn reconstruction_progress(&self) -> HttpResponse {
+        wrong_phase_response("reconstruction/progress", "Configuration")
+    }
+
+    async fn get_model(&self) -> HttpResponse {
+        wrong_phase_response("model", "Configuration")
     }
 
     fn ws_notification(&self, _req: HttpRequest, _stream: web::Payload) -> HttpResponse {
-        endpoint_not_found_in_phase("ws_notification", "Configuration")
+        wrong_phase_response("ws_notification", "Configuration")
+    }
+
+    fn livestream_url(&self) -> Option<reqwest::Url> {
+        None
     }
 }
 
 pub struct ImagePhase {
     new_status_notifier: Arc<std::sync::Mutex<Option<Addr<MyWs>>>>,
     image_downloader: Arc<ImageDownloader>,
+    order: Order,
+    settings: ScanSettings,
 }
 
 impl ImagePhase {
-    async fn new(url: String, rounds: Vec<i32>) -> Result<ImagePhase, Box<dyn Error + Send>> {
-        server_com::post_auftrag(com_model::Auftrag::from_vec(rounds.clone()), &url).await?;
+    async fn new(order: Order, settings: ScanSettings) -> Result<ImagePhase, Box<dyn Error + Send>> {
         let new_status_notifier = Arc::new(Mutex::new(None));
-        println!("new image donwloader");
-        let image_downloader = Arc::new(ImageDownloader::new(
-            url.clone(),
-            com_model::Auftrag::from_vec(rounds.clone()).into_target_status(),
-            Arc::clone(&new_status_notifier)).await?);
-        println!("did not faile");
-        Arc::clone(&image_downloader).start().await;
+        let image_downloader = ImageDownloader::start_auftrag(
+            &order.server_url,
+            order.rounds.clone(),
+            settings,
+            Arc::clone(&new_status_notifier)).await?;
         Ok(ImagePhase {
             new_status_notifier,
             image_downloader,
+            order,
+            settings,
         })
     }
+
+    fn order_info(&self) -> OrderInfo {
+        OrderInfo {
+            order: self.order.clone(),
+            expected_images: self.image_downloader.expected_images(),
+        }
+    }
 }
 
 #[async_trait]
 impl AppState for ImagePhase {
-    async fn index(&self) -> HttpResponse {
-        render_page("html/image_phase_page.html")
+    async fn page_view(&self) -> PageView {
+        PageView::ImagePhase(ImagePhaseContext {
+            server_url: self.image_downloader.server_url().to_string(),
+            status: self.image_downloader.get_status().await,
+            image_count: self.image_downloader.get_image_list().await.len(),
+            excluded_count: self.image_downloader.get_excluded_list().await.len(),
+            expected_images: self.image_downloader.expected_images(),
+        })
+    }
+
+    async fn session(&self) -> HttpResponse {
+        HttpResponse::Ok().json(SessionInfo {
+            phase: Phase::Capture,
+            server_url: Some(self.image_downloader.server_url().to_string()),
+        })
     }
 
     async fn status(&self) -> HttpResponse {
-        let status = self.image_downloader.get_status().await;
+        let status = ImagePhaseStatus {
+            status: self.image_downloader.get_status().await,
+            downloads: self.image_downloader.get_download_stats().await,
+        };
         HttpResponse::Ok().json(status)
     }
 
-    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
-        self.image_downloader.reset().await;
-        (Box::new(Start {}), redirect_response("/"))
+    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, Result<ResetCleanup, HttpResponse>) {
+        let image_downloader = Arc::clone(&self.image_downloader);
+        let cleanup = async move { image_downloader.cancel().await }.boxed().shared();
+        // keeps cancelling even if the client of the reset request goes away
+        actix_web::rt::spawn(cleanup.clone().map(drop));
+        let start = Start {
+            settings: self.settings,
+            server_url: Some(self.image_downloader.server_url().to_string()),
+            previous_scan: Some(cleanup.clone()),
+        };
+        (Box::new(start), Ok(cleanup))
     }
 
-    async fn post_page_form(self: Box<Self>, _page_form: PageForm) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+    async fn post_page_form(self: Box<Self>, page_form: PageForm) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        let force = match page_form {
+            PageForm::StartReconstruction { force } => force.is_some(),
+            page_form => return (self, wrong_phase_response(page_form.action(), "ImagePhase")),
+        };
+        let (app_state, res) = self.start_reconstruction(force).await;
+        (app_state, redirect_on_success(res))
+    }
+
+    async fn post_order(self: Box<Self>, _order: Order) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, error_response(ErrorKind::WrongPhase, "an Auftrag is already running"))
+    }
+
+    async fn order(&self) -> HttpResponse {
+        HttpResponse::Ok().json(self.order_info())
+    }
+
+    async fn start_reconstruction(self: Box<Self>, force: bool) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        let quality_report = self.image_downloader.get_quality_report().await;
+        if quality_report.verdict == Readiness::NotReady && !force {
+            let flagged = quality_report.images.iter().filter(|image| !image.flags.is_empty()).count();
+            return (self, error_response(ErrorKind::InvalidState, format!(
+                "the images are not ready for the reconstruction, {} of {} are flagged by the quality check; \
+                 force the start to reconstruct anyway",
+                flagged, quality_report.images.len())));
+        }
         let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
-        let photogrammetry_phase = PhotogrammetryPhase::new(sender);
+        let photogrammetry_phase = PhotogrammetryPhase::new(sender, self.settings);
         start_photogrammetry(
             Arc::clone(&photogrammetry_phase.new_status),
             Arc::clone(&photogrammetry_phase.console_output),
             receiver,
         ).await;
-        (Box::new(photogrammetry_phase), redirect_response("/"))
-        // {
-        //     Ok(_) => {(Box::new(photogrammetry_phase), redirect_response("/"))},
-        //     Err(err) => {(self, HttpResponse::InternalServerError().body(err.to_string()))}
+        (Box::new(photogrammetry_phase), HttpResponse::Accepted().finish())
     }
 
-    async fn get_content(&self) -> HttpResponse {
-        let image_list = self.image_downloader.get_image_list().await
-            .iter()
-            .map(|image_name| format!("/{}/{}", constants::CONTENT, image_name))
-            .collect::<Vec<_>>();
+    async fn finish_reconstruction(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, wrong_phase_response("/model(post)", "ImagePhase"))
+    }
 
-        HttpResponse::Ok().set_header("Content-Type", "text/json")
-            .json(&image_list)
+    async fn get_image_list(&self) -> HttpResponse {
+        let entry = |name: &String, excluded| ImageEntry {
+            name: name.clone(),
+            excluded,
+            url: format!("{}/{}", constants::API_IMAGES, name),
+            thumbnail_url: format!("{}/{}/thumbnail", constants::API_IMAGES, name),
+        };
+        let images = self.image_downloader.get_image_list().await.iter()
+            .map(|name| entry(name, false))
+            .chain(self.image_downloader.get_excluded_list().await.iter().map(|name| entry(name, true)))
+            .collect::<Vec<_>>();
+        HttpResponse::Ok().json(images)
     }
 
     async fn get_specific_content(&self, name: &str) -> HttpResponse {
-        match self.image_downloader.get_image(&name.to_string()).await {
-            Ok(img) => {
-                HttpResponse::Ok().body(actix_web::web::Bytes::from(img))
-            }
-            Err(err) => {
-                match err {
-                    None => {
-                        // image not found in image store
-                        HttpResponse::NotFound().finish()
-                    }
-                    Some(io_error) => {
-                        // error accessing image
-                        HttpResponse::InternalServerError().body(io_error.to_string())
-                    }
-                }
-            }
+        image_response(self.image_downloader.get_image(&name.to_string()).await, None)
+    }
+
+    async fn get_thumbnail(&self, name: &str, size: ThumbnailSize) -> HttpResponse {
+        image_response(
+            self.image_downloader.get_thumbnail(&name.to_string(), size).await,
+            Some("image/jpeg"),
+        )
+    }
+
+    async fn get_metadata(&self) -> HttpResponse {
+        let metadata_list = self.image_downloader.get_metadata_list().await;
+        HttpResponse::Ok().json(metadata_list)
+    }
+
+    async fn get_specific_metadata(&self, name: &str) -> HttpResponse {
+        match self.image_downloader.get_metadata(name).await {
+            Some(metadata) => HttpResponse::Ok().json(metadata),
+            None => error_response(ErrorKind::NotFound, "image not found"),
+        }
+    }
+
+    async fn get_quality_report(&self) -> HttpResponse {
+        let quality_report = self.image_downloader.get_quality_report().await;
+        HttpResponse::Ok().json(quality_report)
+    }
+
+    async fn set_image_excluded(&self, name: &str, exclude: bool) -> HttpResponse {
+        match self.image_downloader.set_excluded(name, exclude).await {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(None) => error_response(ErrorKind::NotFound, "image not found"),
+            Err(Some(io_error)) => error_response(ErrorKind::Internal, io_error.to_string()),
         }
     }
 
+    async fn request_recapture(&self, name: &str) -> HttpResponse {
+        match Arc::clone(&self.image_downloader).request_recapture(name).await {
+            Ok(()) => HttpResponse::Accepted().finish(),
+            Err(None) => error_response(ErrorKind::NotFound, "image not found"),
+            Err(Some(err)) => server_error_response(&err),
+        }
+    }
+
+    async fn get_image_versions(&self, name: &str) -> HttpResponse {
+        match self.image_downloader.get_versions(name).await {
+            Some(versions) => HttpResponse::Ok().json(versions),
+            None => error_response(ErrorKind::NotFound, "image not found"),
+        }
+    }
+
+    async fn pause(&self) -> HttpResponse {
+        match self.image_downloader.pause().await {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(None) => error_response(ErrorKind::InvalidState, "Auftrag is not running"),
+            Err(Some(err)) => server_error_response(&err),
+        }
+    }
+
+    async fn resume(&self) -> HttpResponse {
+        match self.image_downloader.resume().await {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(None) => error_response(ErrorKind::InvalidState, "Auftrag is not paused"),
+            Err(Some(err)) => server_error_response(&err),
+        }
+    }
+
+    async fn reconstruction_log(&self, _from: usize) -> HttpResponse {
+        wrong_phase_response("/reconstruction/log", "ImagePhase")
+    }
+
+    async fn reconstruction_progress(&self) -> HttpResponse {
+        wrong_phase_response("/reconstruction/progress", "ImagePhase")
+    }
+
+    async fn get_model(&self) -> HttpResponse {
+        wrong_phase_response("/model", "ImagePhase")
+    }
+
     fn ws_notification(&self, req: HttpRequest, stream: web::Payload) -> HttpResponse {
         let notifier = Arc::clone(&self.new_status_notifier);
         match ws::start(MyWs::new(notifier), &req, stream)
         {
             Ok(res) => res,
-            Err(err) => HttpResponse::InternalServerError().body(err.to_string())
+            Err(err) => error_response(ErrorKind::Internal, err.to_string())
         }
     }
+
+    fn livestream_url(&self) -> Option<reqwest::Url> {
+        Some(livestream::stream_url(self.image_downloader.server_url()))
+    }
 }
 
 pub struct PhotogrammetryPhase {
     console_output: Arc<tokio::sync::Mutex<Vec<serde_json::Value>>>,
     new_status: NotificationHandle,
     shutdown_tx: tokio::sync::oneshot::Sender<()>,
+    settings: ScanSettings,
 }
 
 impl PhotogrammetryPhase {
-    fn new(sender: tokio::sync::oneshot::Sender<()>) -> PhotogrammetryPhase {
+    fn new(sender: tokio::sync::oneshot::Sender<()>, settings: ScanSettings) -> PhotogrammetryPhase {
         PhotogrammetryPhase {
             console_output: Arc::new(tokio::sync::Mutex::new(Vec::new())),
             new_status: Arc::new(Mutex::new(None)),
             shutdown_tx: sender,
+            settings,
         }
     }
 }
 
 #[async_trait]
 impl AppState for PhotogrammetryPhase {
-    async fn index(&self) -> HttpResponse {
-        render_page("html/photogrammetry_page.html")
+    async fn page_view(&self) -> PageView {
+        PageView::Photogrammetry(PhotogrammetryContext {
+            console_output: self.console_output.lock().await.clone(),
+        })
+    }
+
+    async fn session(&self) -> HttpResponse {
+        HttpResponse::Ok().json(SessionInfo { phase: Phase::Reconstruction, server_url: None })
     }
 
     async fn status(&self) -> HttpResponse {
-        endpoint_not_found_in_phase("status", "PhotogrammetryPhase")
+        wrong_phase_response("/capture/status", "PhotogrammetryPhase")
     }
 
     #[allow(unused_must_use)]
-    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, Result<ResetCleanup, HttpResponse>) {
         if let Err(_err) = self.shutdown_tx.send(()) {
             warn!("photogrammetry process already dead");
         }
-        (Box::new(Start {}), redirect_response("/"))
+        (Box::new(Start::new(self.settings)), Ok(idle_cleanup()))
     }
 
-    async fn post_page_form(self: Box<Self>, _page_form: PageForm) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
-        (Box::new(ModelPhase {}), redirect_response("/"))
+    async fn post_page_form(self: Box<Self>, page_form: PageForm) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        if !matches!(page_form, PageForm::FinishReconstruction) {
+            return (self, wrong_phase_response(page_form.action(), "PhotogrammetryPhase"));
+        }
+        let (app_state, res) = self.finish_reconstruction().await;
+        (app_state, redirect_on_success(res))
     }
 
-    async fn get_content(&self) -> HttpResponse {
-        log::error!("get_content_lock");
-        let body = self.console_output.lock().await.deref().clone();
-        log::error!("release_content_lock");
+    async fn post_order(self: Box<Self>, _order: Order) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, wrong_phase_response("/order(post)", "PhotogrammetryPhase"))
+    }
 
-        HttpResponse::Ok().json(body)
+    async fn order(&self) -> HttpResponse {
+        wrong_phase_response("/order", "PhotogrammetryPhase")
+    }
+
+    async fn start_reconstruction(self: Box<Self>, _force: bool) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, error_response(ErrorKind::WrongPhase, "the reconstruction is already running"))
+    }
+
+    async fn finish_reconstruction(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (Box::new(ModelPhase { settings: self.settings }), HttpResponse::NoContent().finish())
+    }
+
+    async fn get_image_list(&self) -> HttpResponse {
+        wrong_phase_response("/images", "PhotogrammetryPhase")
     }
 
     async fn get_specific_content(&self, _name: &str) -> HttpResponse {
-        endpoint_not_found_in_phase("/media_content/{content_name}", "PhotogrammetryPhase")
+        wrong_phase_response("/images/{image_name}", "PhotogrammetryPhase")
+    }
+
+    async fn get_thumbnail(&self, _name: &str, _size: ThumbnailSize) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/thumbnail", "PhotogrammetryPhase")
+    }
+
+    async fn get_metadata(&self) -> HttpResponse {
+        wrong_phase_response("/images/metadata", "PhotogrammetryPhase")
+    }
+
+    async fn get_specific_metadata(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/metadata", "PhotogrammetryPhase")
+    }
+
+    async fn get_quality_report(&self) -> HttpResponse {
+        wrong_phase_response("/images/quality", "PhotogrammetryPhase")
+    }
+
+    async fn set_image_excluded(&self, _name: &str, _exclude: bool) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/exclude", "PhotogrammetryPhase")
+    }
+
+    async fn request_recapture(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/recapture", "PhotogrammetryPhase")
+    }
+
+    async fn get_image_versions(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/versions", "PhotogrammetryPhase")
+    }
+
+    async fn pause(&self) -> HttpResponse {
+        wrong_phase_response("/pause", "PhotogrammetryPhase")
+    }
+
+    async fn resume(&self) -> HttpResponse {
+        wrong_phase_response("/resume", "PhotogrammetryPhase")
+    }
+
+    async fn reconstruction_log(&self, from: usize) -> HttpResponse {
+        let console_output = self.console_output.lock().await;
+        HttpResponse::Ok().json(ReconstructionLog {
+            lines: console_output.iter().skip(from).cloned().collect(),
+            next: console_output.len(),
+        })
+    }
+
+    async fn reconstruction_progress(&self) -> HttpResponse {
+        let progress = photogrammetry::progress(&self.console_output.lock().await);
+        HttpResponse::Ok().json(progress)
+    }
+
+    async fn get_model(&self) -> HttpResponse {
+        wrong_phase_response("/model", "PhotogrammetryPhase")
     }
 
     fn ws_notification(&self, req: HttpRequest, stream: Payload) -> HttpResponse {
@@ -272,50 +639,134 @@ impl AppState for PhotogrammetryPhase {
         match ws::start(MyWs::new(notifier), &req, stream)
         {
             Ok(res) => res,
-            Err(err) => HttpResponse::InternalServerError().body(err.to_string())
+            Err(err) => error_response(ErrorKind::Internal, err.to_string())
         }
     }
+
+    fn livestream_url(&self) -> Option<reqwest::Url> {
+        None
+    }
 }
 
-pub struct ModelPhase {}
+pub struct ModelPhase {
+    settings: ScanSettings,
+}
 
 #[async_trait]
 impl AppState for ModelPhase {
-    async fn index(&self) -> HttpResponse {
-        render_page("html/model_page.html")
+    async fn page_view(&self) -> PageView {
+        PageView::Model(ModelContext {
+            model_size: tokio::fs::metadata(paths::archive_file()).await.ok().map(|metadata| metadata.len()),
+        })
+    }
+
+    async fn session(&self) -> HttpResponse {
+        HttpResponse::Ok().json(SessionInfo { phase: Phase::Model, server_url: None })
     }
 
     async fn status(&self) -> HttpResponse {
-        endpoint_not_found_in_phase("/status", "Model")
+        wrong_phase_response("/capture/status", "Model")
     }
 
-    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
-        (Box::new(Start {}), redirect_response("/"))
+    async fn reset(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, Result<ResetCleanup, HttpResponse>) {
+        (Box::new(Start::new(self.settings)), Ok(idle_cleanup()))
     }
 
     async fn post_page_form(self: Box<Self>, _page_form: PageForm) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
-        (self, endpoint_not_found_in_phase("/page_form(post)", "Model"))
+        (self, wrong_phase_response("/page_form(post)", "Model"))
+    }
+
+    async fn post_order(self: Box<Self>, _order: Order) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, wrong_phase_response("/order(post)", "Model"))
+    }
+
+    async fn order(&self) -> HttpResponse {
+        wrong_phase_response("/order", "Model")
+    }
+
+    async fn start_reconstruction(self: Box<Self>, _force: bool) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, wrong_phase_response("/reconstruction(post)", "Model"))
+    }
+
+    async fn finish_reconstruction(self: Box<Self>) -> (Box<dyn AppState + Sync + Send>, HttpResponse) {
+        (self, error_response(ErrorKind::WrongPhase, "the reconstruction is already finished"))
+    }
+
+    async fn get_image_list(&self) -> HttpResponse {
+        wrong_phase_response("/images", "Model")
+    }
+
+    async fn get_specific_content(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}", "Model")
+    }
+
+    async fn get_thumbnail(&self, _name: &str, _size: ThumbnailSize) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/thumbnail", "Model")
+    }
+
+    async fn get_metadata(&self) -> HttpResponse {
+        wrong_phase_response("/images/metadata", "Model")
+    }
+
+    async fn get_specific_metadata(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/metadata", "Model")
+    }
+
+    async fn get_quality_report(&self) -> HttpResponse {
+        wrong_phase_response("/images/quality", "Model")
+    }
+
+    async fn set_image_excluded(&self, _name: &str, _exclude: bool) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/exclude", "Model")
+    }
+
+    async fn request_recapture(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/recapture", "Model")
+    }
+
+    async fn get_image_versions(&self, _name: &str) -> HttpResponse {
+        wrong_phase_response("/images/{image_name}/versions", "Model")
     }
 
-    async fn get_content(&self) -> HttpResponse {
+    async fn pause(&self) -> HttpResponse {
+        wrong_phase_response("/pause", "Model")
+    }
+
+    async fn resume(&self) -> HttpResponse {
+        wrong_phase_response("/resume", "Model")
+    }
+
+    async fn reconstruction_log(&self, _from: usize) -> HttpResponse {
+        wrong_phase_response("/reconstruction/log", "Model")
+    }
+
+    async fn reconstruction_progress(&self) -> HttpResponse {
+        wrong_phase_response("/reconstruction/progress", "Model")
+    }
+
+    async fn get_model(&self) -> HttpResponse {
         //return 3d model as zip
-        match tokio::fs::read("/model.zip").await {
+        match tokio::fs::read(paths::archive_file()).await {
             Ok(file) => {
                 HttpResponse::Ok()
-                    .header("Content-Type", "application/octet-stream")
+                    .header("Content-Type", "application/zip")
+                    .header("Content-Disposition", "attachment; filename=\"model.zip\"")
                     .body(file)
             }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                error_response(ErrorKind::NotFound, "the model archive was not created yet")
+            }
             Err(err) => {
-                HttpResponse::InternalServerError().body(err.to_string())
+                error_response(ErrorKind::Internal, err.to_string())
             }
         }
     }
 
-    async fn get_specific_content(&self, _name: &str) -> HttpResponse {
-        endpoint_not_found_in_phase("/media_content/{content_id}", "Model")
+    fn ws_notification(&self, _req: HttpRequest, _stream: Payload) -> HttpResponse {
+        wrong_phase_response("/ws_notification", "Model")
     }
 
-    fn ws_notification(&self, _req: HttpRequest, _stream: Payload) -> HttpResponse {
-        endpoint_not_found_in_phase("/ws_notification", "Model")
+    fn livestream_url(&self) -> Option<reqwest::Url> {
+        None
     }
 }
\ No newline at end of file
@@ -0,0 +1,111 @@
+use actix_web::HttpResponse;
+use actix_web::web::Bytes;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// files below html/static, compiled into the binary
+const STATIC_FILES: &[(&str, &[u8])] = &[
+    ("api_docs.js", include_bytes!("../../html/static/api_docs.js")),
+    ("bootstrap.bundle.min.js", include_bytes!("../../html/static/bootstrap.bundle.min.js")),
+    ("bootstrap.min.css", include_bytes!("../../html/static/bootstrap.min.css")),
+    ("csrf_forms.js", include_bytes!("../../html/static/csrf_forms.js")),
+    ("favicon.ico", include_bytes!("../../html/static/favicon.ico")),
+    ("jquery-3.5.1.min.js", include_bytes!("../../html/static/jquery-3.5.1.min.js")),
+    // Swagger UI 3.45.1 for the api documentation
+    ("swagger-ui-bundle.js", include_bytes!("../../html/static/swagger-ui-bundle.js")),
+    ("swagger-ui.css", include_bytes!("../../html/static/swagger-ui.css")),
+];
+const STATIC_FOLDER: &str = "static";
+// the content behind a hashed url never changes
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const REVALIDATE: &str = "no-cache";
+
+/// Where the files of the web interface are loaded from.
+#[derive(Clone, Debug)]
+pub enum HtmlSource {
+    // compiled into the binary, independent of the working directory
+    Embedded,
+    // read on every request, so changes show up without restarting
+    Directory(PathBuf),
+}
+
+/// Serves bootstrap, jquery, Swagger UI, the scripts of the pages and the favicon. Embedded files are addressed by the hash of
+/// their content, so browsers can keep them until the binary changes.
+pub struct StaticAssets {
+    source: HtmlSource,
+    hashes: HashMap<&'static str, String>,
+}
+
+impl StaticAssets {
+    pub fn new(source: HtmlSource) -> StaticAssets {
+        let hashes = STATIC_FILES.iter()
+            .map(|(name, content)| (*name, content_hash(content)))
+            .collect();
+        StaticAssets { source, hashes }
+    }
+
+    pub fn url(&self, name: &str) -> String {
+        match (&self.source, self.hashes.get(name)) {
+            (HtmlSource::Embedded, Some(hash)) => format!("/{}/{}/{}", STATIC_FOLDER, hash, name),
+            _ => format!("/{}/{}", STATIC_FOLDER, name),
+        }
+    }
+
+    /// Urls of all assets for the templates, a file name like `bootstrap.min.css`
+    /// becomes the key `bootstrap_min_css`.
+    pub fn urls(&self) -> HashMap<String, String> {
+        STATIC_FILES.iter()
+            .map(|(name, _)| (name.replace(['.', '-'], "_"), self.url(name)))
+            .collect()
+    }
+
+    /// Responds with the asset, `hash` is the one from the url if it has one.
+    pub async fn response(&self, name: &str, hash: Option<&str>) -> HttpResponse {
+        let content = match &self.source {
+            HtmlSource::Embedded => STATIC_FILES.iter()
+                .find(|(file_name, _)| *file_name == name)
+                .map(|(_, content)| Bytes::from_static(content)),
+            HtmlSource::Directory(dir) if is_file_name(name) => {
+                tokio::fs::read(dir.join(STATIC_FOLDER).join(name)).await.ok().map(Bytes::from)
+            }
+            HtmlSource::Directory(_) => None,
+        };
+        let content = match content {
+            Some(content) => content,
+            None => return HttpResponse::NotFound().finish(),
+        };
+        // an outdated hash must not pin the current content in the cache
+        let current = matches!(self.source, HtmlSource::Embedded)
+            && hash.is_some()
+            && hash == self.hashes.get(name).map(String::as_str);
+        HttpResponse::Ok()
+            .content_type(content_type(name))
+            .header("Cache-Control", if current { IMMUTABLE } else { REVALIDATE })
+            .body(content)
+    }
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit('.').next() {
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("ico") => "image/x-icon",
+        Some("html") => "text/html; charset=utf-8",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+// keeps requests inside the static folder
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+// FNV-1a, stable between builds unlike the hasher of the standard library
+fn content_hash(content: &[u8]) -> String {
+    let hash = content.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
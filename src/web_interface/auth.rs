@@ -0,0 +1,392 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::error::BlockingError;
+use actix_web::cookie::SameSite;
+use actix_web::http::{header, Cookie, Method};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use crate::web_interface::api;
+use crate::web_interface::app_state::error_response;
+use crate::web_interface::model::{Credentials, ErrorKind};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use log::info;
+
+pub const SESSION_COOKIE: &str = "scaned_session";
+pub const LOGIN_PATH: &str = "/login";
+pub const LOGOUT_PATH: &str = "/logout";
+// sessions end after this time without a request
+const SESSION_IDLE_TIMEOUT: u64 = 12 * 60 * 60; // in seconds
+// name of the identity of a static token
+const TOKEN_USER: &str = "token";
+
+/// What a user may do, every role includes the ones before it.
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // status, gallery and model
+    Viewer,
+    // orders and photogrammetry
+    Operator,
+    // reset and the sessions of other users
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role {}, expected viewer, operator or admin", role)),
+        }
+    }
+}
+
+/// Where users and tokens come from, authentication is off if both are empty.
+#[derive(Clone, Debug, Default)]
+pub struct AuthConfig {
+    // lines of name:role:argon2 hash
+    pub users_file: Option<PathBuf>,
+    pub tokens: Vec<(Role, String)>,
+}
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("unable to read user file {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("invalid line {1} in user file {0}: {2}")]
+    InvalidLine(PathBuf, usize, String),
+}
+
+/// The user a request was made by, stored in the extensions of the request.
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Session or api token handed out on login.
+#[derive(Serialize, JsonSchema)]
+pub struct TokenInfo {
+    pub token: String,
+    #[serde(flatten)]
+    pub identity: Identity,
+    // the token expires after this time without a request
+    pub idle_timeout: u64, // in seconds
+}
+
+/// Logged in user, without the token.
+#[derive(Serialize, JsonSchema)]
+pub struct SessionEntry {
+    #[serde(flatten)]
+    pub identity: Identity,
+    pub idle_for: u64, // in seconds
+}
+
+struct Session {
+    identity: Identity,
+    last_used: Instant,
+}
+
+struct User {
+    role: Role,
+    password_hash: String,
+}
+
+/// Checks passwords and tokens and keeps the sessions of logged in users in memory.
+pub struct Authenticator {
+    users: HashMap<String, User>,
+    tokens: Vec<(Role, String)>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Result<Authenticator, AuthError> {
+        let users = match &config.users_file {
+            Some(users_file) => read_users(users_file)?,
+            None => HashMap::new(),
+        };
+        Ok(Authenticator { users, tokens: config.tokens.clone(), sessions: Mutex::new(HashMap::new()) })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.users.is_empty() || !self.tokens.is_empty()
+    }
+
+    pub fn has_users(&self) -> bool {
+        !self.users.is_empty()
+    }
+
+    /// Checks the password of a user, an empty name checks a static token instead.
+    /// Verifying a hash takes a while, so this is meant for a blocking thread.
+    pub fn check_credentials(&self, name: &str, password: &str) -> Option<Identity> {
+        if name.is_empty() {
+            return self.token_identity(password);
+        }
+        let user = self.users.get(name)?;
+        let password_hash = PasswordHash::new(&user.password_hash).ok()?;
+        Argon2::default().verify_password(password.as_bytes(), &password_hash).ok()?;
+        Some(Identity { name: name.to_string(), role: user.role })
+    }
+
+    pub fn start_session(&self, identity: Identity) -> TokenInfo {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        self.sessions.lock().unwrap()
+            .insert(token.clone(), Session { identity: identity.clone(), last_used: Instant::now() });
+        TokenInfo { token, identity, idle_timeout: SESSION_IDLE_TIMEOUT }
+    }
+
+    pub fn end_session(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+
+    /// Logs out every user, returns the number of ended sessions.
+    pub fn end_all_sessions(&self) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let count = sessions.len();
+        sessions.clear();
+        count
+    }
+
+    pub fn sessions(&self) -> Vec<SessionEntry> {
+        let mut sessions = self.sessions.lock().unwrap();
+        remove_expired(&mut sessions);
+        sessions.values()
+            .map(|session| SessionEntry {
+                identity: session.identity.clone(),
+                idle_for: session.last_used.elapsed().as_secs(),
+            })
+            .collect()
+    }
+
+    fn session_identity(&self, token: &str) -> Option<Identity> {
+        let mut sessions = self.sessions.lock().unwrap();
+        remove_expired(&mut sessions);
+        let session = sessions.get_mut(token)?;
+        session.last_used = Instant::now();
+        Some(session.identity.clone())
+    }
+
+    fn token_identity(&self, token: &str) -> Option<Identity> {
+        self.tokens.iter()
+            .find(|(_, known)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|(role, _)| Identity { name: TOKEN_USER.to_string(), role: *role })
+    }
+
+    fn identity(&self, req: &impl HttpMessage) -> Option<Identity> {
+        if let Some(token) = bearer_token(req) {
+            return self.token_identity(&token).or_else(|| self.session_identity(&token));
+        }
+        self.session_identity(req.cookie(SESSION_COOKIE)?.value())
+    }
+}
+
+/// Checks the credentials on a blocking thread and starts a session for them.
+pub async fn login(authenticator: web::Data<Authenticator>, credentials: Credentials) -> Result<TokenInfo, HttpResponse> {
+    if !authenticator.is_enabled() {
+        return Err(error_response(ErrorKind::NotFound, "authentication is not configured"));
+    }
+    let checker = authenticator.clone();
+    let checked = web::block(move || {
+        checker.check_credentials(&credentials.username, &credentials.password).ok_or(())
+    }).await;
+    match checked {
+        Ok(identity) => {
+            info!("{} logged in as {}", identity.name, identity.role.as_str());
+            Ok(authenticator.start_session(identity))
+        }
+        Err(BlockingError::Error(())) => Err(error_response(ErrorKind::Unauthorized, "invalid credentials")),
+        Err(BlockingError::Canceled) => Err(error_response(ErrorKind::Internal, "credential check was cancelled")),
+    }
+}
+
+/// Token of the session a request belongs to, from the Authorization header or the cookie.
+pub fn request_token(req: &HttpRequest) -> Option<String> {
+    bearer_token(req).or_else(|| req.cookie(SESSION_COOKIE).map(|cookie| cookie.value().to_string()))
+}
+
+fn bearer_token(req: &impl HttpMessage) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix("Bearer ").map(|token| token.trim().to_string())
+}
+
+/// Cookie of the session, `secure` for requests which came in over https. Browsers leave it out of
+/// posts from other sites, links from other sites still open the pages logged in.
+pub fn session_cookie(token: &str, secure: bool) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, token.to_string())
+        .path("/")
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+fn remove_expired(sessions: &mut HashMap<String, Session>) {
+    sessions.retain(|_, session| session.last_used.elapsed() < Duration::from_secs(SESSION_IDLE_TIMEOUT));
+}
+
+// compares tokens without revealing through timing how much of them matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn read_users(users_file: &Path) -> Result<HashMap<String, User>, AuthError> {
+    let content = std::fs::read_to_string(users_file)
+        .map_err(|err| AuthError::Io(users_file.to_path_buf(), err))?;
+    let mut users = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |message: String| AuthError::InvalidLine(users_file.to_path_buf(), index + 1, message);
+        let mut parts = line.splitn(3, ':');
+        let (name, role, password_hash) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(role), Some(password_hash)) if !name.is_empty() => (name, role, password_hash),
+            _ => return Err(invalid("expected name:role:hash".to_string())),
+        };
+        let role = role.parse::<Role>().map_err(invalid)?;
+        PasswordHash::new(password_hash).map_err(|err| invalid(format!("invalid argon2 hash: {}", err)))?;
+        users.insert(name.to_string(), User { role, password_hash: password_hash.to_string() });
+    }
+    Ok(users)
+}
+
+/// Argon2 hash of a password in the format of the user file.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+// None for routes anyone may use, the login itself and what the login page needs
+fn required_role(method: &Method, path: &str, query: &str) -> Option<Role> {
+    if path == LOGIN_PATH || path == LOGOUT_PATH || path == api::TOKEN_PATH || path.starts_with("/static/") {
+        return None;
+    }
+    let admin_only = path == api::SESSION_PATH || path.starts_with(api::AUTH_SESSIONS_PATH);
+    // the client fetches these urls itself, viewers must not reach other hosts in the network through it
+    let contacts_server = path.starts_with("/server/") || takes_url(query);
+    Some(match *method {
+        Method::GET | Method::HEAD if path.starts_with(api::AUTH_SESSIONS_PATH) => Role::Admin,
+        Method::GET | Method::HEAD if contacts_server => Role::Operator,
+        Method::GET | Method::HEAD => Role::Viewer,
+        Method::DELETE if admin_only => Role::Admin,
+        _ => Role::Operator,
+    })
+}
+
+// decoded like the handlers do, an unreadable query counts as one with a url
+fn takes_url(query: &str) -> bool {
+    web::Query::<HashMap<String, String>>::from_query(query)
+        .map_or(true, |params| params.contains_key("url"))
+}
+
+/// Lets the request through if its user has the role the route needs. Pages send users
+/// who are not logged in to the login page, everything else is answered with 401.
+pub fn authorize(req: &ServiceRequest) -> Result<(), HttpResponse> {
+    let authenticator = match req.app_data::<web::Data<Authenticator>>() {
+        Some(authenticator) if authenticator.is_enabled() => authenticator,
+        _ => return Ok(()),
+    };
+    let identity = authenticator.identity(req);
+    if let Some(identity) = &identity {
+        req.extensions_mut().insert(identity.clone());
+    }
+    // the router matches the percent-decoded path, so the roles are looked up by it as well
+    let path = req.match_info().path();
+    let required_role = match required_role(req.method(), path, req.query_string()) {
+        Some(required_role) => required_role,
+        None => return Ok(()),
+    };
+    match identity {
+        Some(identity) if identity.role >= required_role => Ok(()),
+        Some(identity) => Err(error_response(
+            ErrorKind::Forbidden,
+            format!("{} {} needs the role {}, {} is {}",
+                    req.method(), path, required_role.as_str(), identity.name, identity.role.as_str()))),
+        None if *req.method() == Method::GET && accepts_html(req) => Err(HttpResponse::SeeOther()
+            .header(header::LOCATION, LOGIN_PATH)
+            .finish()),
+        None => {
+            let mut res = error_response(ErrorKind::Unauthorized, "login required");
+            res.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+            Err(res)
+        }
+    }
+}
+
+fn accepts_html(req: &ServiceRequest) -> bool {
+    req.headers().get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{authorize, required_role, AuthConfig, Authenticator, Role};
+    use actix_web::http::{header, Method, StatusCode};
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+
+    fn authorize_as(role: Role, method: Method, uri: &str) -> Result<(), StatusCode> {
+        let tokens = vec![(Role::Viewer, "viewer".to_string()), (Role::Operator, "operator".to_string()),
+                          (Role::Admin, "admin".to_string())];
+        let authenticator = Authenticator::new(&AuthConfig { users_file: None, tokens }).unwrap();
+        let req = TestRequest::with_uri(uri)
+            .method(method)
+            .header(header::AUTHORIZATION, format!("Bearer {}", role.as_str()))
+            .app_data(web::Data::new(authenticator))
+            .to_srv_request();
+        authorize(&req).map_err(|res| res.status())
+    }
+
+    #[test]
+    fn roles_of_the_routes() {
+        assert_eq!(required_role(&Method::GET, "/login", ""), None);
+        assert_eq!(required_role(&Method::POST, "/api/v1/auth/token", ""), None);
+        assert_eq!(required_role(&Method::GET, "/static/favicon.ico", ""), None);
+        assert_eq!(required_role(&Method::GET, "/", ""), Some(Role::Viewer));
+        assert_eq!(required_role(&Method::GET, "/api/v1/capture/status", ""), Some(Role::Viewer));
+        assert_eq!(required_role(&Method::POST, "/page_form", ""), Some(Role::Operator));
+        assert_eq!(required_role(&Method::DELETE, "/api/v1/images/a.jpg/exclude", ""), Some(Role::Operator));
+        assert_eq!(required_role(&Method::DELETE, "/api/v1/session", ""), Some(Role::Admin));
+        assert_eq!(required_role(&Method::GET, "/api/v1/auth/sessions", ""), Some(Role::Admin));
+        assert_eq!(required_role(&Method::DELETE, "/api/v1/auth/sessions", ""), Some(Role::Admin));
+        assert_eq!(required_role(&Method::GET, "/livestream", ""), Some(Role::Viewer));
+        assert_eq!(required_role(&Method::GET, "/livestream", "url=http%3A%2F%2F10.0.0.1%2F"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::GET, "/livestream", "%75rl=http%3A%2F%2F10.0.0.1%2F"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::GET, "/server/discover", ""), Some(Role::Operator));
+        assert_eq!(required_role(&Method::GET, "/server/check", "url=10.0.0.1"), Some(Role::Operator));
+    }
+
+    #[test]
+    fn encoded_paths_need_the_same_role() {
+        assert_eq!(authorize_as(Role::Operator, Method::DELETE, "/api/v1/%73ession"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(authorize_as(Role::Viewer, Method::GET, "/api/v1/auth/%73essions"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(authorize_as(Role::Operator, Method::DELETE, "/api/v1/sess%69on"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(authorize_as(Role::Admin, Method::DELETE, "/api/v1/%73ession"), Ok(()));
+        assert_eq!(authorize_as(Role::Viewer, Method::GET, "/api/v1/capture/%73tatus"), Ok(()));
+    }
+}
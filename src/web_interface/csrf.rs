@@ -0,0 +1,69 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, Method};
+use actix_web::{HttpRequest, HttpResponse};
+use crate::web_interface::app_state::error_response;
+use crate::web_interface::auth;
+use crate::web_interface::model::ErrorKind;
+use log::warn;
+use reqwest::Url;
+use ring::hmac;
+use ring::rand::SystemRandom;
+
+/// Issues the tokens the forms of the pages are posted with and checks them. A token is the hmac of the
+/// session token, so it changes with every login, without authentication all visitors share one token.
+pub struct CsrfGuard {
+    key: hmac::Key,
+}
+
+impl CsrfGuard {
+    pub fn new() -> CsrfGuard {
+        let key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+            .expect("the system random number generator failed");
+        CsrfGuard { key }
+    }
+
+    pub fn token(&self, req: &HttpRequest) -> String {
+        let session = auth::request_token(req).unwrap_or_default();
+        hmac::sign(&self.key, session.as_bytes()).as_ref().iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Answers with 403 unless the token was issued for the session of the request.
+    pub fn verify(&self, req: &HttpRequest, token: &str) -> Result<(), HttpResponse> {
+        if ring::constant_time::verify_slices_are_equal(self.token(req).as_bytes(), token.as_bytes()).is_ok() {
+            return Ok(());
+        }
+        warn!("rejected {} {} with an invalid csrf token", req.method(), req.path());
+        Err(error_response(ErrorKind::Forbidden, "invalid csrf token, reload the page and try again"))
+    }
+}
+
+/// Rejects requests which change something if the browser says they were sent by another site. Scripts send
+/// neither Origin nor Referer and are let through, the session cookie is not sent along cross-site anyway.
+pub fn check_origin(req: &ServiceRequest) -> Result<(), HttpResponse> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+    let origin = match req.headers().get(header::ORIGIN).or_else(|| req.headers().get(header::REFERER)) {
+        Some(origin) => origin.to_str().unwrap_or_default(),
+        None => return Ok(()),
+    };
+    // only host and port are compared, a proxy in front may end https without telling
+    let host = req.connection_info().host().to_string();
+    if authority(origin).is_some_and(|authority| authority.eq_ignore_ascii_case(&host)) {
+        return Ok(());
+    }
+    warn!("rejected {} {} from {}", req.method(), req.path(), origin);
+    Err(error_response(ErrorKind::Forbidden, format!("requests from {} are not allowed", origin)))
+}
+
+// host and non-default port of an origin or referer, None for "null" and other opaque origins
+fn authority(origin: &str) -> Option<String> {
+    let url = Url::parse(origin).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
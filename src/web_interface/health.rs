@@ -0,0 +1,48 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::time::{Duration, Instant};
+use actix_web::web::Bytes;
+use futures::Stream;
+use actix_web::rt::time::delay_for;
+use crate::server_com::ScanEdClient;
+use crate::server_com::com_model::ServerStatus;
+
+pub const DEFAULT_HEALTH_INTERVAL: u64 = 5; // in seconds
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct ConnectionCheck {
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+    pub status: Option<ServerStatus>,
+    pub error: Option<String>,
+}
+
+pub async fn check_connection(client: &ScanEdClient) -> ConnectionCheck {
+    let start = Instant::now();
+    match client.get_status().await {
+        Ok(status) => ConnectionCheck {
+            reachable: true,
+            latency_ms: Some(start.elapsed().as_millis() as u64),
+            status: Some(status.position),
+            error: None,
+        },
+        Err(err) => ConnectionCheck {
+            reachable: false,
+            latency_ms: None,
+            status: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+/// Server-sent events with a connection check every interval, ends when the page is closed.
+pub fn health_stream(client: ScanEdClient, interval: Duration) -> impl Stream<Item=Result<Bytes, actix_web::Error>> + Unpin {
+    Box::pin(futures::stream::unfold((client, true), move |(client, first)| async move {
+        if !first {
+            delay_for(interval).await;
+        }
+        let connection_check = check_connection(&client).await;
+        let event = format!("data: {}\n\n", serde_json::to_string(&connection_check).unwrap());
+        Some((Ok(Bytes::from(event)), (client, false)))
+    }))
+}
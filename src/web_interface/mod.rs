@@ -1,2 +1,10 @@
+pub mod api;
 pub mod app_state;
-pub mod model;
\ No newline at end of file
+pub mod assets;
+pub mod auth;
+pub mod csrf;
+pub mod health;
+pub mod model;
+pub mod openapi;
+pub mod templates;
+pub mod tls;
\ No newline at end of file
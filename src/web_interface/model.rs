@@ -1,20 +1,54 @@
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use crate::server_com::com_model::ServerStatus;
 use std::error::Error;
 use std::sync::Arc;
 use actix::Addr;
 use crate::web_interface::model::ws::MyWs;
+use crate::photogrammetry::thumbnails::ThumbnailSize;
+use crate::photogrammetry::downloads::DownloadStats;
+use crate::photogrammetry::photogrammetry::Message;
 
 pub type NotificationHandle = Arc<std::sync::Mutex<Option<Addr<MyWs>>>>;
 
-#[derive(Deserialize)]
-#[serde(tag = "type")]
+/// Action of a form of the pages, every action belongs to the phase whose page shows the form.
+#[derive(Deserialize, JsonSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
 pub enum PageForm {
     Auftrag(Auftrag),
-    None,
+    StartReconstruction {
+        // checkbox of the page, present if the quality verdict is overridden
+        #[serde(default)]
+        force: Option<String>,
+    },
+    FinishReconstruction,
+}
+
+impl PageForm {
+    pub fn action(&self) -> &'static str {
+        match self {
+            PageForm::Auftrag(_) => "auftrag",
+            PageForm::StartReconstruction { .. } => "start_reconstruction",
+            PageForm::FinishReconstruction => "finish_reconstruction",
+        }
+    }
+}
+
+/// Posted by the forms of the pages, the action only runs with the csrf token of the page.
+#[derive(Deserialize, JsonSchema)]
+pub struct PageFormPost {
+    pub csrf_token: String,
+    #[serde(flatten)]
+    pub form: PageForm,
 }
 
-#[derive(Deserialize, Clone)]
+/// Form without fields besides the csrf token, such as the logout button.
+#[derive(Deserialize, JsonSchema)]
+pub struct CsrfForm {
+    pub csrf_token: String,
+}
+
+#[derive(Deserialize, JsonSchema, Clone)]
 pub struct Auftrag {
     input_runde1: String,
     input_runde2: String,
@@ -30,17 +64,186 @@ impl Auftrag {
             self.input_runde3.parse::<i32>().map_err(|err| -> Box<dyn Error> {err.into()})?])
     }
 
-    pub fn get_url(&self) -> &String {
-        &self.input_hostname
+    pub fn into_order(self) -> Result<Order, Box<dyn Error>> {
+        let server_url = self.input_hostname.clone();
+        Ok(Order { server_url, rounds: self.into_vec()? })
     }
 }
 
-#[derive(Serialize, Clone, PartialEq)]
+/// Auftrag as posted to the api, every entry of rounds is the number of shots of a round.
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+pub struct Order {
+    pub server_url: String,
+    pub rounds: Vec<i32>,
+}
+
+impl Order {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rounds.iter().any(|shots| *shots < 0) {
+            return Err("the number of shots of a round must not be negative".to_string());
+        }
+        if self.rounds.iter().all(|shots| *shots == 0) {
+            return Err("Auftrag without images".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+pub enum Phase {
+    Configuration,
+    Capture,
+    Reconstruction,
+    Model,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SessionInfo {
+    pub phase: Phase,
+    // server of the running Auftrag, or of the previous one after a reset
+    pub server_url: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct OrderInfo {
+    #[serde(flatten)]
+    pub order: Order,
+    pub expected_images: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ImageEntry {
+    pub name: String,
+    pub excluded: bool,
+    pub url: String,
+    pub thumbnail_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct ThumbnailQuery {
+    pub size: Option<ThumbnailSize>,
+}
+
+#[derive(Deserialize)]
+pub struct ReconstructionQuery {
+    // starts even if the quality verdict is NotReady
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Deserialize)]
+pub struct LogQuery {
+    // index of the first line returned
+    pub from: Option<usize>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ReconstructionLog {
+    #[schemars(with = "Vec<Message>")]
+    pub lines: Vec<serde_json::Value>,
+    // index to continue reading from
+    pub next: usize,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    BadRequest,
+    // no or an expired session or token
+    Unauthorized,
+    // the role of the user is not sufficient
+    Forbidden,
+    NotFound,
+    // the endpoint is not available in the current phase
+    WrongPhase,
+    // the request does not fit the state of the Auftrag
+    InvalidState,
+    ServerError,
+    ServerTimeout,
+    Internal,
+}
+
+/// Body of every error response.
+#[derive(Serialize, JsonSchema)]
+pub struct ErrorBody {
+    pub error: ErrorKind,
+    pub message: String,
+}
+
+/// Login of the html page and of the api, an empty username logs in with a static token.
+#[derive(Deserialize, JsonSchema)]
+pub struct Credentials {
+    #[serde(default)]
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ServerUrlQuery {
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+pub struct LiveStreamQuery {
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct HealthMonitorQuery {
+    pub url: String,
+    pub interval: Option<u64>, // in seconds
+}
+
+#[derive(Serialize, JsonSchema, Clone, PartialEq)]
 #[serde(tag = "type")]
 pub enum ImageAppStatus {
     Start,
     TakingImages(ServerStatus),
     Finished,
+    // the Auftrag did not finish within the scan timeout
+    TimedOut,
+    // the server holds the Auftrag until it is resumed
+    Paused,
+}
+
+impl ImageAppStatus {
+    // nothing is downloaded anymore, apart from recaptures
+    pub fn is_done(&self) -> bool {
+        matches!(self, ImageAppStatus::Finished | ImageAppStatus::TimedOut)
+    }
+}
+
+/// Status of the image phase together with the progress of the downloads.
+#[derive(Serialize, JsonSchema)]
+pub struct ImagePhaseStatus {
+    #[serde(flatten)]
+    pub status: ImageAppStatus,
+    pub downloads: DownloadStats,
+}
+
+/// Outcome of resetting a running scan.
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct ResetReport {
+    // polling stopped and no download is running anymore
+    pub client_idle: bool,
+    pub aborted_downloads: usize,
+    // the server accepted the abbruch request
+    pub server_cancelled: bool,
+    pub server_idle: bool,
+    pub server_error: Option<String>,
+}
+
+impl ResetReport {
+    /// Report of the phases after the scan, the server finished the Auftrag already.
+    pub fn idle() -> ResetReport {
+        ResetReport {
+            client_idle: true,
+            aborted_downloads: 0,
+            server_cancelled: false,
+            server_idle: true,
+            server_error: None,
+        }
+    }
 }
 
 pub mod ws {
@@ -48,6 +251,7 @@ pub mod ws {
     use actix_web_actors::ws;
     use std::sync::{Arc, Mutex};
 
+    #[allow(dead_code)]
     #[derive(Message, Clone)]
     #[rtype(result = "()")]
     struct Msg(String);
@@ -68,6 +272,7 @@ pub mod ws {
 
     /// Handler for ws::Message message
     impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWs {
+        #[allow(clippy::explicit_auto_deref)]
         fn handle(
             &mut self,
             msg: Result<ws::Message, ws::ProtocolError>,
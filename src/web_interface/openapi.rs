@@ -0,0 +1,384 @@
+use crate::endpoints;
+use crate::web_interface::api::{self, Route};
+use crate::web_interface::auth::SESSION_COOKIE;
+use crate::web_interface::model::ErrorBody;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+pub const SPEC_PATH: &str = "/api/openapi.json";
+
+/// Collects the operations of the routing tables, the schemas of their bodies are generated
+/// from the serde types and shared under components.
+pub struct SpecBuilder {
+    generator: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl SpecBuilder {
+    fn new() -> SpecBuilder {
+        SpecBuilder { generator: SchemaSettings::openapi3().into_generator(), paths: Map::new() }
+    }
+
+    pub fn schema<T: JsonSchema>(&mut self) -> Value {
+        json!(self.generator.subschema_for::<T>())
+    }
+
+    pub fn json_content<T: JsonSchema>(&mut self) -> Value {
+        json!({ "application/json": { "schema": self.schema::<T>() } })
+    }
+
+    // prefix is the scope the routes are registered in
+    fn add_routes(&mut self, prefix: &str, routes: &[Route]) {
+        for route in routes {
+            self.add(&format!("{}{}", prefix, route.path), route);
+        }
+    }
+
+    fn add(&mut self, path: &str, route: &Route) {
+        let mut operation = (route.operation)(self);
+        let error = self.json_content::<ErrorBody>();
+        operation["operationId"] = json!(route.handler);
+        operation["summary"] = json!(route.summary);
+        operation["tags"] = json!([tag(path)]);
+        operation["responses"]["default"] = json!({
+            "description": "Fehler, 409 wenn der Endpunkt in der aktuellen Phase nicht verfügbar ist, \
+                            401 ohne und 403 mit unzureichender Anmeldung",
+            "content": error,
+        });
+        let path_item = self.paths.entry(path.to_string()).or_insert_with(|| json!({}));
+        path_item[route.method.as_str().to_ascii_lowercase()] = operation;
+    }
+
+    fn finish(self) -> Value {
+        let schemas = self.generator.definitions().iter()
+            .map(|(name, schema)| (name.clone(), json!(schema)))
+            .collect::<Map<_, _>>();
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "ScanEd Client",
+                "version": env!("CARGO_PKG_VERSION"),
+                "description": "Steuerung von Aufnahme, Photogrammetrie und Modell. \
+                                Jede Phase bietet nur ihre eigenen Endpunkte an. \
+                                Ist die Anmeldung aktiviert, lesen Viewer, Operatoren starten Aufträge \
+                                und Photogrammetrie, Admins setzen zurück und beenden Sitzungen.",
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "securitySchemes": {
+                    "token": { "type": "http", "scheme": "bearer" },
+                    "session": { "type": "apiKey", "in": "cookie", "name": SESSION_COOKIE },
+                },
+            },
+            // the empty requirement allows requests while the authentication is off
+            "security": [{ "token": [] }, { "session": [] }, {}],
+        })
+    }
+}
+
+// first path segment below the api prefix
+fn tag(path: &str) -> &str {
+    path.strip_prefix(api::PREFIX)
+        .and_then(|path| path.trim_start_matches('/').split('/').next())
+        .unwrap_or("html")
+}
+
+pub fn ok(description: &str, content: Value) -> Value {
+    json!({ "description": description, "content": content })
+}
+
+pub fn empty(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+pub fn binary(content_type: &str) -> Value {
+    json!({ content_type: { "schema": { "type": "string", "format": "binary" } } })
+}
+
+pub fn image_name() -> Value {
+    json!({ "name": "image_name", "in": "path", "required": true, "schema": { "type": "string" } })
+}
+
+/// OpenAPI 3 document of the json api and of the page routes the html pages use.
+pub fn spec() -> Value {
+    let mut spec = SpecBuilder::new();
+    spec.add_routes(api::PREFIX, api::ROUTES);
+    spec.add_routes("", endpoints::ROUTES);
+    spec.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spec, SPEC_PATH};
+    use crate::endpoints;
+    use crate::web_interface::api;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::{test, App};
+    use crate::photogrammetry::downloads::{DownloadState, DownloadStats, ImageDownload};
+    use crate::photogrammetry::photogrammetry::{self, Message};
+    use crate::discovery::DiscoveredServer;
+    use crate::livestream::snapshot::{ExposureHint, PreviewReport};
+    use crate::server_com::com_model::{ServerInfo, ServerStatus};
+    use crate::web_interface::health::ConnectionCheck;
+    use crate::web_interface::auth::{Identity, Role, SessionEntry, TokenInfo};
+    use crate::web_interface::model::{ErrorBody, ErrorKind, ImageAppStatus, ImageEntry, ImagePhaseStatus, Order, OrderInfo,
+                                      PageForm, PageFormPost, Phase, ReconstructionLog, ResetReport, SessionInfo};
+    use schemars::JsonSchema;
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use std::collections::BTreeSet;
+
+    // checks a value against a schema of the spec, properties which are not in the
+    // schema count as drift as well
+    fn validate(spec: &Value, value: &Value, schema: &Value) -> Result<(), String> {
+        let declared = check(spec, value, schema)?;
+        if let (Value::Object(object), Some(declared)) = (value, declared) {
+            if let Some(key) = object.keys().find(|key| !declared.contains(*key)) {
+                return Err(format!("property {} is missing in the schema", key));
+            }
+        }
+        Ok(())
+    }
+
+    // returns the declared properties of an object, None if any property is allowed
+    fn check(spec: &Value, value: &Value, schema: &Value) -> Result<Option<BTreeSet<String>>, String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return check(spec, value, &spec["components"]["schemas"][name]);
+        }
+        if value.is_null() && schema["nullable"] == true {
+            return Ok(None);
+        }
+        if let Some(variants) = schema["enum"].as_array() {
+            if !variants.contains(value) {
+                return Err(format!("{} is not one of {:?}", value, variants));
+            }
+        }
+        let mut declared = BTreeSet::new();
+        let mut any_property = schema["properties"].is_null() && schema["allOf"].is_null() && schema["oneOf"].is_null();
+        if let Some(parts) = schema["allOf"].as_array() {
+            for part in parts {
+                match check(spec, value, part)? {
+                    Some(properties) => declared.extend(properties),
+                    None => any_property = true,
+                }
+            }
+        }
+        if let Some(variants) = schema["oneOf"].as_array().or_else(|| schema["anyOf"].as_array()) {
+            let matching = variants.iter()
+                .filter_map(|variant| check(spec, value, variant).ok())
+                .collect::<Vec<_>>();
+            match matching.as_slice() {
+                [Some(properties)] => declared.extend(properties.iter().cloned()),
+                [None] => any_property = true,
+                _ => return Err(format!("{} matches {} variants of {}", value, matching.len(), schema)),
+            }
+        }
+        match schema["type"].as_str() {
+            Some("object") => {
+                let object = value.as_object().ok_or_else(|| format!("{} is not an object", value))?;
+                for required in schema["required"].as_array().into_iter().flatten() {
+                    let required = required.as_str().unwrap_or_default();
+                    if !object.contains_key(required) {
+                        return Err(format!("{} misses the required property {}", value, required));
+                    }
+                }
+                for (key, property) in object {
+                    if let Some(property_schema) = schema["properties"].get(key) {
+                        validate(spec, property, property_schema)?;
+                        declared.insert(key.clone());
+                    } else if schema["additionalProperties"].is_object() {
+                        validate(spec, property, &schema["additionalProperties"])?;
+                        declared.insert(key.clone());
+                    }
+                }
+            }
+            Some("array") => {
+                let items = value.as_array().ok_or_else(|| format!("{} is not an array", value))?;
+                for item in items {
+                    validate(spec, item, &schema["items"])?;
+                }
+            }
+            Some("string") if !value.is_string() => return Err(format!("{} is not a string", value)),
+            Some("boolean") if !value.is_boolean() => return Err(format!("{} is not a boolean", value)),
+            Some("integer") if !(value.is_i64() || value.is_u64()) => return Err(format!("{} is not an integer", value)),
+            Some("number") if !value.is_number() => return Err(format!("{} is not a number", value)),
+            _ => {}
+        }
+        Ok(if any_property { None } else { Some(declared) })
+    }
+
+    fn assert_matches<T: JsonSchema + Serialize>(spec: &Value, sample: &T) {
+        let schema = json!({ "$ref": format!("#/components/schemas/{}", T::schema_name()) });
+        let value = json!(sample);
+        if let Err(err) = validate(spec, &value, &schema) {
+            panic!("{} drifted from its schema: {}\nvalue: {}", T::schema_name(), err, value);
+        }
+    }
+
+    #[test]
+    fn serialized_types_match_the_spec() {
+        let spec = spec();
+        let status = ImageAppStatus::TakingImages(ServerStatus { runde: 2, aufnahme: 5 });
+        for status in [ImageAppStatus::Start, status.clone(), ImageAppStatus::Finished,
+                       ImageAppStatus::TimedOut, ImageAppStatus::Paused] {
+            assert_matches(&spec, &status);
+        }
+        assert_matches(&spec, &ImagePhaseStatus {
+            status,
+            downloads: DownloadStats {
+                queued: 1,
+                active: 1,
+                retrying: 0,
+                completed: 3,
+                failed: 1,
+                cancelled: 0,
+                bytes_per_second: 2048,
+                pending: vec![ImageDownload {
+                    path: "/aufnahme/runde1/img_1.jpg".to_string(),
+                    name: "runde1_img_1.jpg".to_string(),
+                    state: DownloadState::Failed,
+                    attempts: 5,
+                    bytes_received: 0,
+                    last_error: Some("timeout".to_string()),
+                }],
+            },
+        });
+        assert_matches(&spec, &ErrorBody { error: ErrorKind::WrongPhase, message: "images".to_string() });
+        assert_matches(&spec, &ErrorBody { error: ErrorKind::Forbidden, message: "DELETE /".to_string() });
+        let identity = Identity { name: "anna".to_string(), role: Role::Operator };
+        assert_matches(&spec, &TokenInfo { token: "ab12".to_string(), identity: identity.clone(), idle_timeout: 60 });
+        assert_matches(&spec, &vec![SessionEntry { identity, idle_for: 5 }]);
+        assert_matches(&spec, &SessionInfo { phase: Phase::Capture, server_url: None });
+        assert_matches(&spec, &OrderInfo {
+            order: Order { server_url: "http://192.168.1.2:8000/".to_string(), rounds: vec![8, 8, 4] },
+            expected_images: 20,
+        });
+        assert_matches(&spec, &vec![ImageEntry {
+            name: "img_1.jpg".to_string(),
+            excluded: true,
+            url: "/api/v1/images/img_1.jpg".to_string(),
+            thumbnail_url: "/api/v1/images/img_1.jpg/thumbnail".to_string(),
+        }]);
+        let connection = ConnectionCheck {
+            reachable: true,
+            latency_ms: Some(12),
+            status: Some(ServerStatus { runde: 1, aufnahme: 3 }),
+            error: None,
+        };
+        assert_matches(&spec, &connection);
+        assert_matches(&spec, &vec![DiscoveredServer {
+            name: "scaned".to_string(),
+            url: "http://192.168.1.2:8000/".to_string(),
+            connection,
+        }]);
+        assert_matches(&spec, &ServerInfo::legacy());
+        assert_matches(&spec, &PreviewReport {
+            taken_at: 1_600_000_000,
+            mean_brightness: 0.4,
+            blur: 120.5,
+            histogram: vec![0.5, 0.5],
+            hints: vec![ExposureHint::Dark, ExposureHint::Blurry],
+        });
+        assert_matches(&spec, &ResetReport {
+            client_idle: true,
+            aborted_downloads: 2,
+            server_cancelled: true,
+            server_idle: false,
+            server_error: None,
+        });
+
+        // console lines are stored as json values, the schema documents them as messages
+        let lines = vec![
+            json!(Message::NewConsoleOutput("[INFO]    Running opensfm stage".into())),
+            json!(Message::Error("exited".into())),
+            json!(Message::Finished),
+        ];
+        assert_matches(&spec, &photogrammetry::progress(&lines));
+        assert_matches(&spec, &ReconstructionLog { lines, next: 3 });
+    }
+
+    #[test]
+    fn page_form_matches_the_spec() {
+        let spec = spec();
+        let form = json!({
+            "csrf_token": "0f3a",
+            "action": "auftrag",
+            "input_runde1": "8",
+            "input_runde2": "8",
+            "input_runde3": "4",
+            "input_hostname": "http://192.168.1.2:8000/",
+        });
+        let schema = json!({ "$ref": format!("#/components/schemas/{}", PageFormPost::schema_name()) });
+        validate(&spec, &form, &schema).unwrap();
+        serde_json::from_value::<PageFormPost>(form).unwrap();
+        let form = json!({ "csrf_token": "0f3a", "action": "start_reconstruction" });
+        validate(&spec, &form, &schema).unwrap();
+        serde_json::from_value::<PageFormPost>(form).unwrap();
+        let form = json!({ "csrf_token": "0f3a", "action": "start_reconstruction", "force": "true" });
+        validate(&spec, &form, &schema).unwrap();
+        let form = serde_json::from_value::<PageFormPost>(form).unwrap();
+        assert!(matches!(form.form, PageForm::StartReconstruction { force: Some(_) }));
+        assert!(validate(&spec, &json!({ "csrf_token": "0f3a", "type": "None" }), &schema).is_err());
+    }
+
+    // method, full path and handler of every route of the tables
+    fn table_routes() -> Vec<(String, String, String)> {
+        let tables = [(api::PREFIX, api::ROUTES), ("", endpoints::ROUTES)];
+        tables.iter()
+            .flat_map(|(prefix, routes)| routes.iter().map(move |route| (
+                route.method.as_str().to_ascii_lowercase(),
+                format!("{}{}", prefix, route.path),
+                route.handler.to_string(),
+            )))
+            .collect()
+    }
+
+    fn operations(spec: &Value) -> BTreeSet<(String, String, String)> {
+        let mut operations = BTreeSet::new();
+        for (path, path_item) in spec["paths"].as_object().unwrap() {
+            for (method, operation) in path_item.as_object().unwrap() {
+                operations.insert((method.clone(), path.clone(), operation["operationId"].as_str().unwrap().to_string()));
+            }
+        }
+        operations
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let routes = table_routes();
+        let documented = operations(&spec());
+        // a second route with the same method and path would replace the first in the spec
+        assert_eq!(documented.len(), routes.len(), "routes share a method and path");
+        assert_eq!(routes.into_iter().collect::<BTreeSet<_>>(), documented);
+        let paths = table_routes().into_iter().map(|(_, path, _)| path).collect::<BTreeSet<_>>();
+        for path in &[api::SESSION_PATH, api::TOKEN_PATH, api::AUTH_SESSIONS_PATH] {
+            assert!(paths.contains(*path), "{} is no route", path);
+        }
+        assert!(!paths.contains(SPEC_PATH));
+    }
+
+    #[test]
+    fn every_route_is_registered() {
+        actix_web::rt::System::new("openapi").block_on(async move {
+            let mut app = test::init_service(App::new()
+                .configure(api::configure)
+                .configure(endpoints::configure)).await;
+            for (method, path, handler) in table_routes() {
+                let uri = path.replace("{image_name}", "img_1.jpg");
+                let req = test::TestRequest::with_uri(&uri)
+                    .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                    .to_request();
+                // without app data the handlers fail, unregistered routes end in the default service
+                let res = test::call_service(&mut app, req).await;
+                let status = res.status();
+                let body = test::read_body(res).await;
+                assert!(!String::from_utf8_lossy(&body).contains("no endpoint"), "{} is not registered", handler);
+                assert!(status != StatusCode::NOT_FOUND && status != StatusCode::METHOD_NOT_ALLOWED,
+                        "{} is not registered: {}", handler, status);
+            }
+        });
+    }
+}
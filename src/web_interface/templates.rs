@@ -0,0 +1,245 @@
+use crate::web_interface::assets::HtmlSource;
+use crate::web_interface::auth::{self, Identity};
+use crate::web_interface::model::ImageAppStatus;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use thiserror::Error;
+use tinytemplate::TinyTemplate;
+
+const MASTER_TEMPLATE: &str = "master";
+const MASTER_FILE: &str = "master.html";
+const DOCS_TEMPLATE: &str = "api_docs";
+const DOCS_FILE: &str = "api_docs.html";
+const LOGIN_TEMPLATE: &str = "login";
+const LOGIN_FILE: &str = "login.html";
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("unable to read template {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("invalid template: {0}")]
+    Template(#[from] tinytemplate::error::Error),
+    #[error("unable to serialize page context: {0}")]
+    Context(#[from] serde_json::Error),
+}
+
+#[derive(Serialize)]
+pub struct StartupContext {
+    // server of the previous scan
+    pub server_url: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImagePhaseContext {
+    pub server_url: String,
+    pub status: ImageAppStatus,
+    pub image_count: usize,
+    pub excluded_count: usize,
+    pub expected_images: usize,
+}
+
+#[derive(Serialize)]
+pub struct PhotogrammetryContext {
+    pub console_output: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct ModelContext {
+    pub model_size: Option<u64>, // in bytes
+}
+
+/// A page together with the data it is rendered with.
+pub enum PageView {
+    Startup(StartupContext),
+    ImagePhase(ImagePhaseContext),
+    Photogrammetry(PhotogrammetryContext),
+    Model(ModelContext),
+}
+
+#[derive(Serialize)]
+struct Fact {
+    label: &'static str,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct MasterTemplateContext<'a> {
+    title: &'static str,
+    assets: &'a HashMap<String, String>,
+    facts: Vec<Fact>,
+    // json read by the scripts of the page
+    page_context: String,
+    page_content: &'a str,
+    // None if authentication is off
+    identity: Option<&'a Identity>,
+    logout_path: &'static str,
+    // posted along by every form of the page
+    csrf_token: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct LoginContext {
+    // without users only a token can be entered
+    pub has_users: bool,
+    pub failed: bool,
+}
+
+#[derive(Serialize)]
+struct LoginTemplateContext<'a> {
+    assets: &'a HashMap<String, String>,
+    login_path: &'static str,
+    #[serde(flatten)]
+    login: &'a LoginContext,
+}
+
+#[derive(Serialize)]
+struct DocsTemplateContext<'a> {
+    assets: &'a HashMap<String, String>,
+    spec_url: &'a str,
+}
+
+impl PageView {
+    fn file_name(&self) -> &'static str {
+        match self {
+            PageView::Startup(_) => "startup_page.html",
+            PageView::ImagePhase(_) => "image_phase_page.html",
+            PageView::Photogrammetry(_) => "photogrammetry_page.html",
+            PageView::Model(_) => "model_page.html",
+        }
+    }
+
+    fn embedded_content(&self) -> &'static str {
+        match self {
+            PageView::Startup(_) => include_str!("../../html/startup_page.html"),
+            PageView::ImagePhase(_) => include_str!("../../html/image_phase_page.html"),
+            PageView::Photogrammetry(_) => include_str!("../../html/photogrammetry_page.html"),
+            PageView::Model(_) => include_str!("../../html/model_page.html"),
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            PageView::Startup(_) => "Konfiguration",
+            PageView::ImagePhase(_) => "Aufnahme",
+            PageView::Photogrammetry(_) => "Photogrammetrie",
+            PageView::Model(_) => "Modell",
+        }
+    }
+
+    // rendered above the page content
+    fn facts(&self) -> Vec<Fact> {
+        let fact = |label, value: String| Fact { label, value };
+        match self {
+            PageView::Startup(context) => context.server_url.iter()
+                .map(|server_url| fact("Letzter Server", server_url.clone()))
+                .collect(),
+            PageView::ImagePhase(context) => vec![
+                fact("Server", context.server_url.clone()),
+                fact("Aufnahmen", format!("{} von {}", context.image_count, context.expected_images)),
+                fact("Ausgeschlossen", context.excluded_count.to_string()),
+            ],
+            PageView::Photogrammetry(context) => vec![
+                fact("Ausgabezeilen", context.console_output.len().to_string()),
+            ],
+            PageView::Model(context) => context.model_size.iter()
+                .map(|size| fact("Größe", format!("{:.1} MB", *size as f64 / 1_000_000.0)))
+                .collect(),
+        }
+    }
+
+    fn context_json(&self) -> serde_json::Result<String> {
+        let json = match self {
+            PageView::Startup(context) => serde_json::to_string(context),
+            PageView::ImagePhase(context) => serde_json::to_string(context),
+            PageView::Photogrammetry(context) => serde_json::to_string(context),
+            PageView::Model(context) => serde_json::to_string(context),
+        }?;
+        // the json is placed inside a script element, which must not be closed by the data
+        Ok(json.replace('<', "\\u003c"))
+    }
+}
+
+/// Renders pages into the master template, the login page and the api documentation. Embedded templates are
+/// compiled once when the registry is created, templates from a directory on every render.
+pub struct TemplateRegistry {
+    source: HtmlSource,
+    embedded: TinyTemplate<'static>,
+    // urls of the static assets by template key
+    asset_urls: HashMap<String, String>,
+}
+
+impl TemplateRegistry {
+    pub fn new(source: HtmlSource, asset_urls: HashMap<String, String>) -> Result<TemplateRegistry, TemplateError> {
+        let mut embedded = TinyTemplate::new();
+        embedded.add_template(MASTER_TEMPLATE, include_str!("../../html/master.html"))?;
+        embedded.add_template(DOCS_TEMPLATE, include_str!("../../html/api_docs.html"))?;
+        embedded.add_template(LOGIN_TEMPLATE, include_str!("../../html/login.html"))?;
+        if let HtmlSource::Directory(dir) = &source {
+            // fail at startup rather than on the first request
+            compile_template(MASTER_TEMPLATE, &read_template(dir, MASTER_FILE)?)?;
+            compile_template(DOCS_TEMPLATE, &read_template(dir, DOCS_FILE)?)?;
+            compile_template(LOGIN_TEMPLATE, &read_template(dir, LOGIN_FILE)?)?;
+        }
+        Ok(TemplateRegistry { source, embedded, asset_urls })
+    }
+
+    pub fn render(&self, view: &PageView, identity: Option<&Identity>, csrf_token: &str) -> Result<String, TemplateError> {
+        match &self.source {
+            HtmlSource::Embedded => self.render_master(&self.embedded, view, view.embedded_content(), identity, csrf_token),
+            HtmlSource::Directory(dir) => {
+                let master_text = read_template(dir, MASTER_FILE)?;
+                let page_content = read_template(dir, view.file_name())?;
+                self.render_master(&compile_template(MASTER_TEMPLATE, &master_text)?, view, &page_content, identity, csrf_token)
+            }
+        }
+    }
+
+    pub fn render_login(&self, login: &LoginContext) -> Result<String, TemplateError> {
+        let context = LoginTemplateContext { assets: &self.asset_urls, login_path: auth::LOGIN_PATH, login };
+        match &self.source {
+            HtmlSource::Embedded => Ok(self.embedded.render(LOGIN_TEMPLATE, &context)?),
+            HtmlSource::Directory(dir) => {
+                let login_text = read_template(dir, LOGIN_FILE)?;
+                Ok(compile_template(LOGIN_TEMPLATE, &login_text)?.render(LOGIN_TEMPLATE, &context)?)
+            }
+        }
+    }
+
+    pub fn render_api_docs(&self, spec_url: &str) -> Result<String, TemplateError> {
+        let context = DocsTemplateContext { assets: &self.asset_urls, spec_url };
+        match &self.source {
+            HtmlSource::Embedded => Ok(self.embedded.render(DOCS_TEMPLATE, &context)?),
+            HtmlSource::Directory(dir) => {
+                let docs_text = read_template(dir, DOCS_FILE)?;
+                Ok(compile_template(DOCS_TEMPLATE, &docs_text)?.render(DOCS_TEMPLATE, &context)?)
+            }
+        }
+    }
+
+    fn render_master(&self, master: &TinyTemplate, view: &PageView, page_content: &str,
+                     identity: Option<&Identity>, csrf_token: &str) -> Result<String, TemplateError> {
+        let context = MasterTemplateContext {
+            title: view.title(),
+            assets: &self.asset_urls,
+            facts: view.facts(),
+            page_context: view.context_json()?,
+            page_content,
+            identity,
+            logout_path: auth::LOGOUT_PATH,
+            csrf_token,
+        };
+        Ok(master.render(MASTER_TEMPLATE, &context)?)
+    }
+}
+
+fn compile_template<'t>(name: &'static str, text: &'t str) -> Result<TinyTemplate<'t>, TemplateError> {
+    let mut template = TinyTemplate::new();
+    template.add_template(name, text)?;
+    Ok(template)
+}
+
+fn read_template(dir: &std::path::Path, file_name: &str) -> Result<String, TemplateError> {
+    let path = dir.join(file_name);
+    std::fs::read_to_string(&path).map_err(|err| TemplateError::Io(path, err))
+}
@@ -0,0 +1,140 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header;
+use actix_web::HttpResponse;
+use log::info;
+use rcgen::{Certificate, CertificateParams, DnType, SanType};
+use rustls::internal::pemfile;
+use rustls::{NoClientAuth, ServerConfig};
+use std::io::{BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub const DEFAULT_HTTPS_PORT: u16 = 8443;
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+/// Where the certificate of the https server comes from.
+#[derive(Clone, Debug)]
+pub enum TlsSource {
+    // certificate chain and private key in PEM files
+    Pem { cert: PathBuf, key: PathBuf },
+    // generated on the first start and kept in dir, so browsers only have to trust it once
+    SelfSigned { dir: PathBuf, names: Vec<String> },
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub source: TlsSource,
+    pub https_port: u16,
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("unable to access {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("no certificate or private key found in {0}")]
+    Pem(PathBuf),
+    #[error("unable to generate a certificate: {0}")]
+    Generate(#[from] rcgen::RcgenError),
+    #[error("invalid certificate or private key: {0}")]
+    Rustls(#[from] rustls::TLSError),
+}
+
+/// Loads or generates the certificate and builds the configuration of the https listener.
+pub fn server_config(source: &TlsSource) -> Result<ServerConfig, TlsError> {
+    let (cert, key) = match source {
+        TlsSource::Pem { cert, key } => (cert.clone(), key.clone()),
+        TlsSource::SelfSigned { dir, names } => {
+            let (cert, key) = (dir.join(CERT_FILE), dir.join(KEY_FILE));
+            if !cert.exists() || !key.exists() {
+                generate_self_signed(dir, names)?;
+            }
+            (cert, key)
+        }
+    };
+    let cert_chain = pemfile::certs(&mut open_pem(&cert)?)
+        .ok()
+        .filter(|certs| !certs.is_empty())
+        .ok_or_else(|| TlsError::Pem(cert.clone()))?;
+    // PKCS#8 as written by openssl req and rcgen, PKCS#1 as written by older tools
+    let private_key = pemfile::pkcs8_private_keys(&mut open_pem(&key)?)
+        .ok()
+        .filter(|keys| !keys.is_empty())
+        .or_else(|| pemfile::rsa_private_keys(&mut open_pem(&key).ok()?).ok())
+        .and_then(|keys| keys.into_iter().next())
+        .ok_or_else(|| TlsError::Pem(key.clone()))?;
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(cert_chain, private_key)?;
+    Ok(config)
+}
+
+fn open_pem(path: &Path) -> Result<BufReader<std::fs::File>, TlsError> {
+    std::fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))
+}
+
+fn generate_self_signed(dir: &Path, names: &[String]) -> Result<(), TlsError> {
+    let mut names = names.to_vec();
+    names.push("localhost".to_string());
+    names.push(Ipv4Addr::LOCALHOST.to_string());
+    if let Some(address) = local_address() {
+        names.push(address.to_string());
+    }
+    names.sort();
+    names.dedup();
+    let mut params = CertificateParams::default();
+    params.distinguished_name.push(DnType::CommonName, "ScanEd Client");
+    params.subject_alt_names = names.iter()
+        .map(|name| name.parse::<IpAddr>().map_or_else(|_| SanType::DnsName(name.clone()), SanType::IpAddress))
+        .collect();
+    let certificate = Certificate::from_params(params)?;
+
+    std::fs::create_dir_all(dir).map_err(|err| TlsError::Io(dir.to_path_buf(), err))?;
+    write_file(&dir.join(CERT_FILE), certificate.serialize_pem()?.as_bytes(), 0o644)?;
+    write_file(&dir.join(KEY_FILE), certificate.serialize_private_key_pem().as_bytes(), 0o600)?;
+    info!("generated a self-signed certificate for {} in {}", names.join(", "), dir.display());
+    Ok(())
+}
+
+fn write_file(path: &Path, content: &[u8], mode: u32) -> Result<(), TlsError> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(path)
+        .and_then(|mut file| file.write_all(content))
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))
+}
+
+// address of the interface with the default route, connecting a udp socket sends nothing
+fn local_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Sends requests which came in over plain http to the same url on the https port.
+pub fn https_redirect(req: &ServiceRequest, https_port: u16) -> Option<HttpResponse> {
+    let connection_info = req.connection_info();
+    if connection_info.scheme() == "https" {
+        return None;
+    }
+    let location = format!("https://{}:{}{}",
+                           host_name(connection_info.host()),
+                           https_port,
+                           req.uri().path_and_query().map_or("/", |path| path.as_str()));
+    // 308 keeps the method, so forms and api calls are repeated over https
+    Some(HttpResponse::PermanentRedirect().header(header::LOCATION, location).finish())
+}
+
+// host header without the port, ipv6 addresses keep their brackets
+fn host_name(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.find(']').map_or(host, |end| &host[..=end]);
+    }
+    host.rsplit_once(':').map_or(host, |(name, _)| name)
+}